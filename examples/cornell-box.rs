@@ -1,32 +1,32 @@
 // 这个竟然要60 min……
+// 后来直接采样灯了，100 spp就够了
 
 extern crate ray_tracer;
 
-use ray_tracer::camera::Camera;
+use ray_tracer::aov::Aov;
 use ray_tracer::camera::PerspectiveCamera;
+use ray_tracer::denoise::Denoiser;
 use ray_tracer::geometry::Cube;
 use ray_tracer::geometry::Rectangle;
+use ray_tracer::integrator::LightSamplingPathTracer;
+use ray_tracer::light::Sample;
 use ray_tracer::mat4::Mat4;
 use ray_tracer::material::DiffuseLight;
 use ray_tracer::material::Lambertian;
 use ray_tracer::optimize::AxisAlignedBoundingBox;
 use ray_tracer::optimize::Bound;
 use ray_tracer::optimize::BoundingVolumeHierarchyNode;
-use ray_tracer::render::color;
+use ray_tracer::render::Renderer;
 use ray_tracer::sprite::Sprite;
+use ray_tracer::tonemap::AcesToneMap;
+use ray_tracer::tonemap::DisplayTransform;
 use ray_tracer::vec3::Vec3;
 
-use rand::thread_rng;
-use rand::Rng;
-
 use std::sync::Arc;
 
 fn main() {
     let width = 800;
     let height = 800;
-    println!("P3");
-    println!("{:?} {:?}", width, height);
-    println!("255");
 
     let redMaterial = Arc::new(Lambertian::new(Vec3::new(0.65, 0.05, 0.05)));
     let whiteMaterial = Arc::new(Lambertian::new(Vec3::new(0.73, 0.73, 0.73)));
@@ -49,14 +49,16 @@ fn main() {
                 .multiplied(&Mat4::rotation((90.0 as f64).to_radians(), Vec3::ey())),
         )
         .build();
-    let light = Sprite::builder()
-        .geometry(Rectangle::new(130.0, 105.0).into())
-        .material(lightMaterial.clone())
-        .transform(
-            Mat4::translation(Vec3::new(555.0 / 2.0, 554.0, 555.0 / 2.0))
-                .multiplied(&Mat4::rotation((90.0 as f64).to_radians(), Vec3::ex())),
-        )
-        .build();
+    let light = Arc::new(
+        Sprite::builder()
+            .geometry(Rectangle::new(130.0, 105.0).into())
+            .material(lightMaterial.clone())
+            .transform(
+                Mat4::translation(Vec3::new(555.0 / 2.0, 554.0, 555.0 / 2.0))
+                    .multiplied(&Mat4::rotation((90.0 as f64).to_radians(), Vec3::ex())),
+            )
+            .build(),
+    );
     let floor = Sprite::builder()
         .geometry(Rectangle::new(555.0, 555.0).into())
         .material(whiteMaterial.clone())
@@ -125,7 +127,7 @@ fn main() {
     let world: Vec<Arc<dyn Bound<AxisAlignedBoundingBox>>> = vec![
         Arc::new(greenWall),
         Arc::new(redWall),
-        Arc::new(light),
+        light.clone(),
         Arc::new(floor),
         Arc::new(ceiling),
         Arc::new(backWall),
@@ -133,7 +135,6 @@ fn main() {
         Arc::new(backCube),
     ];
     let world = BoundingVolumeHierarchyNode::new(world).unwrap();
-    let world = Arc::new(world);
 
     let eye = Vec3::new(555.0 / 2.0, 555.0 / 2.0, -800.0);
     let center = Vec3::new(555.0 / 2.0, 555.0 / 2.0, 0.0);
@@ -150,55 +151,18 @@ fn main() {
     );
     let camera = Arc::new(camera);
 
-    let subPixelSampleCount = 1000; // 每个pixel细分成多少个sub pixel
-
-    let (sender, receiver) = std::sync::mpsc::channel();
-    let mut buffer = vec![vec![Vec3::new(0.0, 0.0, 0.0); width]; height];
-    let cpuCount = num_cpus::get();
-
-    for i in 0..cpuCount {
-        let sender = sender.clone();
-        let world = world.clone();
-        let camera = camera.clone();
-
-        std::thread::spawn(move || {
-            for y in (0..height).rev() {
-                if y % cpuCount != i {
-                    continue;
-                }
-
-                for x in 0..width {
-                    let mut pixel = Vec3::new(0.0, 0.0, 0.0);
-                    for _ in 0..subPixelSampleCount {
-                        let mut generator = thread_rng();
-                        let u = (x as f64 + generator.gen_range(0.0, 1.0)) / width as f64;
-                        let v = (y as f64 + generator.gen_range(0.0, 1.0)) / height as f64;
-                        let ray = camera.ray(u, v);
-                        pixel += color(&ray, world.as_ref(), 100);
-                    }
-                    pixel /= subPixelSampleCount as f64;
-                    sender.send((x, y, pixel)).unwrap();
-                }
-            }
-        });
-    }
-
-    for _ in (0..height).rev() {
-        for _ in 0..width {
-            let (x, y, pixel) = receiver.recv().unwrap();
-            buffer[y][x] = pixel;
-        }
-    }
+    // 直接采样灯之后100 spp就差不多和原来1000 spp一样干净了
+    let lights: Vec<Arc<dyn Sample>> = vec![light];
+    let renderer = Renderer::builder(camera, width, height)
+        .subPixelSampleCount(400)
+        .minSampleCount(32)
+        .errorThreshold(0.02)
+        .integrator(Arc::new(LightSamplingPathTracer::new(lights, 5)))
+        .aovs(vec![Aov::Albedo, Aov::Normal, Aov::Depth]) // 降噪要用
+        .build();
+    let film = Denoiser::default().denoise(&renderer.render(&world));
 
-    for y in (0..height).rev() {
-        for x in 0..width {
-            let pixel = &buffer[y][x];
-            println!(
-                "{:?} {:?} {:?}",
-                (pixel.r().sqrt() * 255.0).min(255.0) as usize,
-                (pixel.g().sqrt() * 255.0).min(255.0) as usize,
-                (pixel.b().sqrt() * 255.0).min(255.0) as usize, // 有时候会发现有的像素的rgb值超过了255
-            );
-        }
-    }
+    let transform = DisplayTransform::new(0.0, Arc::new(AcesToneMap));
+    film.writePpm(&mut std::io::stdout().lock(), &transform)
+        .unwrap();
 }
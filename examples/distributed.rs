@@ -0,0 +1,124 @@
+// 分布式渲染：一个coordinator发tile，几个worker进程渲染
+// cargo run --release --example distributed > out.ppm                  在本机开两个worker进程
+// cargo run --release --example distributed coordinator 0.0.0.0:7878   只当coordinator
+// cargo run --release --example distributed worker 192.168.1.2:7878    只当worker，可以在别的机器上跑
+// 所有进程的场景和设置必须一样，所以这里的场景不能用随机数生成
+
+extern crate ray_tracer;
+
+use ray_tracer::camera::PerspectiveCamera;
+use ray_tracer::environment::GradientEnvironment;
+use ray_tracer::geometry::Sphere;
+use ray_tracer::mat4::Mat4;
+use ray_tracer::material::Dielectric;
+use ray_tracer::material::Lambertian;
+use ray_tracer::material::Material;
+use ray_tracer::material::Metal;
+use ray_tracer::optimize::AxisAlignedBoundingBox;
+use ray_tracer::optimize::Bound;
+use ray_tracer::optimize::BoundingVolumeHierarchyNode;
+use ray_tracer::render::Renderer;
+use ray_tracer::sprite::Sprite;
+use ray_tracer::tonemap::DisplayTransform;
+use ray_tracer::vec3::Vec3;
+
+use std::net::TcpListener;
+use std::process::Command;
+use std::sync::Arc;
+
+fn main() {
+    let width = 800;
+    let height = 400;
+
+    let camera = PerspectiveCamera::new(
+        Vec3::new(0.0, 1.0, 5.0),
+        Vec3::new(0.0, 0.5, 0.0),
+        Vec3::ey(),
+        (30.0 as f64).to_radians(),
+        width as f64 / height as f64,
+        5.0,
+        0.0,
+    );
+    let renderer = Renderer::builder(Arc::new(camera), width, height)
+        .environment(Arc::new(GradientEnvironment::new(
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(0.5, 0.7, 1.0),
+        )))
+        .subPixelSampleCount(100)
+        .build();
+
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let arguments: Vec<&str> = arguments.iter().map(|v| v.as_str()).collect();
+    match arguments[..] {
+        ["worker", address] => {
+            renderer.renderWorker(&scene(), address).unwrap();
+        }
+        ["coordinator", address] => {
+            let listener = TcpListener::bind(address).unwrap();
+            write(&renderer, listener);
+        }
+        [] => {
+            // 自己当coordinator，再把自己作为worker启动两遍
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+            let mut workers: Vec<_> = (0..2)
+                .map(|_| {
+                    Command::new(std::env::current_exe().unwrap())
+                        .args(["worker", &address])
+                        .spawn()
+                        .unwrap()
+                })
+                .collect();
+
+            write(&renderer, listener);
+            for worker in workers.iter_mut() {
+                worker.wait().unwrap();
+            }
+        }
+        _ => {
+            eprintln!("usage: distributed [coordinator ADDRESS | worker ADDRESS]");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn write(renderer: &Renderer, listener: TcpListener) {
+    let film = renderer.renderDistributed(listener).unwrap();
+    film.writePpm(&mut std::io::stdout().lock(), &DisplayTransform::default())
+        .unwrap();
+}
+
+fn sphere<U>(center: Vec3, radius: f64, material: U) -> Arc<dyn Bound<AxisAlignedBoundingBox>>
+where
+    U: Material + 'static,
+{
+    return Arc::new(
+        Sprite::builder()
+            .geometry(Sphere::new(radius).into())
+            .material(material.into())
+            .transform(Mat4::translation(center))
+            .build(),
+    );
+}
+
+fn scene() -> BoundingVolumeHierarchyNode<AxisAlignedBoundingBox> {
+    let world = vec![
+        sphere(
+            Vec3::new(0.0, -1000.0, 0.0),
+            1000.0,
+            Lambertian::new(Vec3::new(0.5, 0.5, 0.5)),
+        ),
+        sphere(
+            Vec3::new(-1.1, 0.5, 0.0),
+            0.5,
+            Lambertian::new(Vec3::new(0.4, 0.2, 0.1)),
+        ),
+        sphere(Vec3::new(0.0, 0.5, 0.0), 0.5, Dielectric::new(1.5)),
+        sphere(
+            Vec3::new(1.1, 0.5, 0.0),
+            0.5,
+            Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0),
+        ),
+    ];
+    return BoundingVolumeHierarchyNode::new(world).unwrap();
+}
@@ -1,6 +1,5 @@
 extern crate ray_tracer;
 
-use ray_tracer::camera::Camera; // 非要把trait也import进来才能调用trait方法
 use ray_tracer::camera::PerspectiveCamera;
 use ray_tracer::geometry::Cube;
 use ray_tracer::geometry::Rectangle;
@@ -17,8 +16,11 @@ use ray_tracer::material::Metal;
 use ray_tracer::material::SolidColor;
 use ray_tracer::material::Texture;
 use ray_tracer::ray::Hit;
-use ray_tracer::render::color;
+use ray_tracer::render::Progress;
+use ray_tracer::render::Renderer;
 use ray_tracer::sprite::Sprite;
+use ray_tracer::tonemap::AcesToneMap;
+use ray_tracer::tonemap::DisplayTransform;
 use ray_tracer::vec3::Vec3;
 use ray_tracer::volume::ConstantMedium;
 
@@ -44,7 +46,6 @@ fn main() {
 
     let world = BoundingVolumeHierarchyNode::new(finalScene()).unwrap();
     // eprintln!("{:#?}", world);
-    let world = Arc::new(world);
 
     let eye = Vec3::new(555.0 / 2.0 + 200.0, 550.0 / 2.0, -600.0);
     let center = Vec3::new(555.0 / 2.0, 555.0 / 2.0, 0.0);
@@ -64,65 +65,37 @@ fn main() {
     // 黑色背景下噪点很多，不知道是什么问题
     let subPixelSampleCount = 1000; // 每个pixel细分成多少个sub pixel
 
-    let (sender, receiver) = std::sync::mpsc::channel();
-    // let mut buffer = vec![vec![Vec3::new(0.0, 0.0, 0.0); width]; height];
-    let cpuCount = num_cpus::get();
-
-    for i in 0..cpuCount {
-        let sender = sender.clone();
-        let world = world.clone();
-        let camera = camera.clone();
-
-        std::thread::spawn(move || {
-            for y in (0..height).rev() {
-                if y % cpuCount != i {
-                    continue;
-                }
-
-                for x in 0..width {
-                    let mut pixel = Vec3::new(0.0, 0.0, 0.0);
-                    for _ in 0..subPixelSampleCount {
-                        let mut generator = thread_rng();
-                        let u = (x as f64 + generator.gen_range(0.0, 1.0)) / width as f64;
-                        let v = (y as f64 + generator.gen_range(0.0, 1.0)) / height as f64;
-                        // 这个不太符合OpenGL的normalized device coordinate，啥时候改一下
-                        // let ray = Ray::new(origin, lowerLeft + horizontal * u + vertical * v);
-                        let ray = camera.ray(u, v);
-                        // 书上这里把world变成了一个什么hit_list，我想不如直接给Vec<Box<dyn Hit>>实现Hit trait，这样多个物体和一个物体都满足Hit trait
-                        pixel += color(&ray, world.as_ref(), 100); // 天哪&*是什么玩意，还是用as_ref()吧
-                    }
-                    pixel /= subPixelSampleCount as f64;
-                    sender.send((x, y, pixel)).unwrap();
-                }
-            }
-        });
-    }
-
-    // 改成了4个worker thread，这样可以减少Arc clone的次数
+    // 每渲染完一个tile在stderr上报一下进度
+    let renderer = Renderer::builder(camera, width, height)
+        .subPixelSampleCount(subPixelSampleCount)
+        .progress(Arc::new(|progress: &Progress| {
+            eprint!(
+                "\r{}/{} tiles, {:.0} samples/s, ETA {} s  ",
+                progress.tilesDone(),
+                progress.tileCount(),
+                progress.samplesPerSecond(),
+                progress.eta().map(|v| v.as_secs()).unwrap_or(0),
+            );
+        }))
+        .build();
+    let film = renderer.render(&world);
+    eprintln!();
 
     // 改成输出png了，好像ppm很少有软件能打开
     // image库真难用啊……
     let mut img = image::DynamicImage::new_rgba8(width as u32, height as u32);
+    // 灯比1亮很多，直接截断的话灯周围一片死白，用ACES曲线压一下
+    let transform = DisplayTransform::new(0.0, Arc::new(AcesToneMap));
 
-    for _ in (0..height).rev() {
-        for _ in 0..width {
-            let (x, y, pixel) = receiver.recv().unwrap();
-            // buffer[y][x] = pixel;
+    for y in (0..height).rev() {
+        for x in 0..width {
+            let [r, g, b] = transform.apply8(&film.pixel(x, y));
             use image::GenericImage;
             img.put_pixel(
                 x as u32,
                 (height - 1 - y) as u32,
-                image::Rgba([
-                    (pixel.r().sqrt() * 255.0).min(255.0) as u8,
-                    (pixel.g().sqrt() * 255.0).min(255.0) as u8,
-                    (pixel.b().sqrt() * 255.0).min(255.0) as u8,
-                    255,
-                ]),
+                image::Rgba([r, g, b, 255]),
             );
-
-            if x == 0 {
-                eprintln!("{:#?} {:#?}", y, x);
-            }
         }
     }
 
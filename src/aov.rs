@@ -0,0 +1,72 @@
+use crate::ray::HitRecord;
+use crate::vec3::Vec3;
+
+// 除了颜色以外顺便输出的东西（arbitrary output variable），合成和降噪的时候要用
+// 都是从相机发出的光线第一次打到的表面上取的，每一种存成单独一张film
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Aov {
+    Depth,       // 光线的t，相机光线的方向是单位向量，所以就是到相机的距离
+    Normal,      // 世界坐标系里的法向量
+    Albedo,      // 材质本身的颜色，见Material::reflectance()
+    Uv,          // 材质坐标，放在前两个通道
+    ObjectId,    // Sprite的编号
+    SampleCount, // 这个pixel一共采了多少个样本，不是从表面上取的，Renderer直接填
+}
+
+impl Aov {
+    // 也是Film::aov()里用的名字
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Uv => "uv",
+            Aov::ObjectId => "objectId",
+            Aov::SampleCount => "sampleCount",
+        }
+    }
+
+    // 物体边缘的pixel里，深度和编号取平均会得到一个哪个物体都不是的值，所以只取每个pixel的第一个样本
+    // 其他的和颜色一样，所有样本取平均，边缘是抗锯齿过的。被镜头挡住的样本什么都没看到，两种都不算
+    pub fn isAveraged(&self) -> bool {
+        return !matches!(self, Aov::Depth | Aov::ObjectId | Aov::SampleCount);
+    }
+
+    // 一个样本的值，什么都没打到的话是0。SampleCount和样本打到什么无关，总是0
+    pub fn value(&self, record: Option<&HitRecord>) -> Vec3 {
+        let record = match record {
+            Some(record) => record,
+            None => return Vec3::new(0.0, 0.0, 0.0),
+        };
+
+        match self {
+            Aov::Depth => {
+                return Vec3::new(record.t(), record.t(), record.t());
+            }
+            Aov::Normal => {
+                // Sprite变换过的法向量不一定是单位向量，烟雾里面的法向量可能是0
+                if record.normal().length() == 0.0 {
+                    return Vec3::new(0.0, 0.0, 0.0);
+                }
+                return record.normal().normalized();
+            }
+            Aov::Albedo => {
+                if let Some(material) = record.material() {
+                    return material.reflectance(record.uv(), record.intersection());
+                } else {
+                    return Vec3::new(0.0, 0.0, 0.0);
+                }
+            }
+            Aov::Uv => {
+                return Vec3::new(record.uv().0, record.uv().1, 0.0);
+            }
+            Aov::ObjectId => {
+                let id = record.objectId() as f64;
+                return Vec3::new(id, id, id);
+            }
+            Aov::SampleCount => {
+                return Vec3::new(0.0, 0.0, 0.0);
+            }
+        }
+    }
+}
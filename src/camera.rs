@@ -1,9 +1,70 @@
+use crate::environment::EquirectangularEnvironment;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::util::randomInRegularPolygon;
 use crate::util::randomInUnitDisk;
 use crate::vec3::Vec3;
 
+use std::f64::consts::PI;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+
 pub trait Camera: Send + Sync {
-    fn ray(&self, u: f64, v: f64) -> Ray;
+    fn ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Ray;
+
+    // 带权重的光线，Renderer用的是这个。被镜头挡住了就是None，这个样本算黑的
+    // 真实的镜头画面边缘会暗一些（渐晕），就是靠挡住的光线和权重算出来的，一般的相机权重都是1
+    fn weightedRay(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<(Ray, f64)> {
+        return Some((self.ray(u, v, sampler), 1.0));
+    }
+}
+
+// 给任意一个相机加上快门，动起来的物体会糊成一片。和TransformedGeometry一样是对原来相机的修饰，这样每个相机都不用自己管快门
+// 快门从open开到close，光线的时间在这中间均匀地取。没开快门（open == close）的话不用sampler，原来的随机序列不会变
+#[derive(Clone, Debug)]
+pub struct ShutterCamera<T> {
+    camera: T,
+    open: f64,
+    close: f64,
+}
+
+impl<T> ShutterCamera<T> {
+    pub fn new(camera: T, open: f64, close: f64) -> Self {
+        Self {
+            camera: camera,
+            open: open,
+            close: close,
+        }
+    }
+
+    pub fn camera(&self) -> &T {
+        return &self.camera;
+    }
+
+    pub fn shutter(&self) -> (f64, f64) {
+        return (self.open, self.close);
+    }
+
+    fn time(&self, sampler: &mut dyn Sampler) -> f64 {
+        if self.close > self.open {
+            return self.open + (self.close - self.open) * sampler.next1D();
+        } else {
+            return self.open;
+        }
+    }
+}
+
+impl<T: Camera> Camera for ShutterCamera<T> {
+    fn ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Ray {
+        let ray = self.camera.ray(u, v, sampler);
+        return ray.withTime(self.time(sampler));
+    }
+
+    fn weightedRay(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<(Ray, f64)> {
+        let (ray, weight) = self.camera.weightedRay(u, v, sampler)?;
+        return Some((ray.withTime(self.time(sampler)), weight));
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -88,15 +149,17 @@ impl PerspectiveCamera {
 }
 
 impl Camera for PerspectiveCamera {
-    fn ray(&self, u: f64, v: f64) -> Ray {
+    fn ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Ray {
         if self.lensRadius == 0.0 {
             return Ray::new(
                 self.eye,
                 (self.lowerLeft + self.horizontal * u + self.vertical * v - self.eye).normalized(), // 这里direction要不要normalize呢……如果normalize，有一个好处是t就有非常明确的物理含义了，如果我们算出射线上某个点的t，就能确定这个点离射线的起点正好是t米
             );
         } else {
-            let rd = self.lensRadius * randomInUnitDisk();
-            let offset = rd.x() * u + rd.y() * v;
+            // 镜头上的点要沿着相机的水平和竖直方向偏移，原来乘的是画面坐标u、v，相当于只在(1, 1, 1)方向上抖动
+            let rd = self.lensRadius * randomInUnitDisk(sampler);
+            let offset =
+                self.horizontal.normalized() * rd.x() + self.vertical.normalized() * rd.y();
             return Ray::new(
                 self.eye + offset,
                 (self.lowerLeft + self.horizontal * u + self.vertical * v - self.eye - offset)
@@ -105,3 +168,1090 @@ impl Camera for PerspectiveCamera {
         }
     }
 }
+
+// 按真实相机的参数来的薄透镜相机：焦距和传感器尺寸是毫米，光圈是f值，对焦距离和场景一样用场景的单位
+// 视角和光圈大小都是算出来的：
+// - 对焦在d的时候传感器离镜头1/f - 1/d的倒数那么远（薄透镜公式），视角由传感器尺寸和这个距离决定，所以对焦越近视角越小一点
+// - 光圈直径是焦距除以f值
+// 场景单位默认是米，不是的话用metersPerUnit换算，不然光圈大小就不对了
+// bladeCount是光圈叶片数，0是圆形光圈，3个以上的话焦外的光斑是正多边形
+#[derive(Clone, Debug)]
+pub struct ThinLensCamera {
+    eye: Vec3,
+    center: Vec3,
+    up: Vec3,
+    focalLength: f64,   // 毫米
+    fNumber: f64,       // 0是针孔，没有景深
+    sensorWidth: f64,   // 毫米
+    sensorHeight: f64,  // 毫米，画面宽高比就是sensorWidth / sensorHeight
+    focusDistance: f64, // 场景单位
+    bladeCount: usize,
+    bladeRotation: f64, // 第一片叶片的角度，弧度
+    metersPerUnit: f64,
+    fov: f64,
+    apertureRadius: f64, // 场景单位
+    lowerLeft: Vec3,
+    horizontal: Vec3,
+    vertical: Vec3,
+}
+
+pub struct ThinLensCameraBuilder {
+    camera: ThinLensCamera,
+}
+
+impl ThinLensCameraBuilder {
+    // 对焦距离不比焦距远的话成不了像，返回InvalidInput。默认对焦在center上，所以eye和center重合也不行
+    pub fn build(mut self) -> Result<ThinLensCamera> {
+        self.camera.update()?;
+        return Ok(self.camera);
+    }
+
+    pub fn focalLength(mut self, focalLength: f64) -> Self {
+        self.camera.focalLength = focalLength;
+        return self;
+    }
+
+    pub fn fNumber(mut self, fNumber: f64) -> Self {
+        self.camera.fNumber = fNumber;
+        return self;
+    }
+
+    // 全画幅是36 x 24，APS-C大概是23.6 x 15.6
+    pub fn sensor(mut self, width: f64, height: f64) -> Self {
+        self.camera.sensorWidth = width;
+        self.camera.sensorHeight = height;
+        return self;
+    }
+
+    pub fn focusDistance(mut self, focusDistance: f64) -> Self {
+        self.camera.focusDistance = focusDistance;
+        return self;
+    }
+
+    pub fn blades(mut self, bladeCount: usize, bladeRotation: f64) -> Self {
+        self.camera.bladeCount = bladeCount;
+        self.camera.bladeRotation = bladeRotation;
+        return self;
+    }
+
+    pub fn metersPerUnit(mut self, metersPerUnit: f64) -> Self {
+        self.camera.metersPerUnit = metersPerUnit;
+        return self;
+    }
+}
+
+impl ThinLensCamera {
+    // 默认是全画幅50mm f/2.8，对焦在center上
+    pub fn builder(eye: Vec3, center: Vec3, up: Vec3) -> ThinLensCameraBuilder {
+        ThinLensCameraBuilder {
+            camera: ThinLensCamera {
+                eye: eye,
+                center: center,
+                up: up.normalized(),
+                focalLength: 50.0,
+                fNumber: 2.8,
+                sensorWidth: 36.0,
+                sensorHeight: 24.0,
+                focusDistance: (center - eye).length(),
+                bladeCount: 0,
+                bladeRotation: 0.0,
+                metersPerUnit: 1.0,
+                fov: 0.0,
+                apertureRadius: 0.0,
+                lowerLeft: Vec3::new(0.0, 0.0, 0.0),
+                horizontal: Vec3::new(0.0, 0.0, 0.0),
+                vertical: Vec3::new(0.0, 0.0, 0.0),
+            },
+        }
+    }
+
+    // 算视角、光圈和对焦平面上的画面
+    fn update(&mut self) -> Result<()> {
+        let millimetersPerUnit = self.metersPerUnit * 1000.0;
+        let focusDistance = self.focusDistance * millimetersPerUnit;
+        if focusDistance <= self.focalLength || focusDistance.is_nan() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "cannot focus closer than the focal length",
+            ));
+        }
+
+        let imageDistance = 1.0 / (1.0 / self.focalLength - 1.0 / focusDistance);
+        self.fov = 2.0 * (self.sensorHeight / 2.0 / imageDistance).atan();
+        if self.fNumber > 0.0 {
+            self.apertureRadius = self.focalLength / self.fNumber / 2.0 / millimetersPerUnit;
+        } else {
+            self.apertureRadius = 0.0;
+        }
+
+        // 传感器经过镜头中心投影到对焦平面上，和PerspectiveCamera一样在对焦平面上放画面
+        let scale = self.focusDistance / imageDistance;
+        let w = (self.eye - self.center).normalized();
+        let u = self.up.cross(&w).normalized();
+        let v = w.cross(&u);
+        self.horizontal = u * self.sensorWidth * scale;
+        self.vertical = v * self.sensorHeight * scale;
+        self.lowerLeft =
+            self.eye - self.horizontal / 2.0 - self.vertical / 2.0 - w * self.focusDistance;
+        return Ok(());
+    }
+
+    pub fn eye(&self) -> &Vec3 {
+        return &self.eye;
+    }
+
+    pub fn center(&self) -> &Vec3 {
+        return &self.center;
+    }
+
+    pub fn up(&self) -> &Vec3 {
+        return &self.up;
+    }
+
+    pub fn focalLength(&self) -> f64 {
+        return self.focalLength;
+    }
+
+    pub fn fNumber(&self) -> f64 {
+        return self.fNumber;
+    }
+
+    pub fn sensorWidth(&self) -> f64 {
+        return self.sensorWidth;
+    }
+
+    pub fn sensorHeight(&self) -> f64 {
+        return self.sensorHeight;
+    }
+
+    pub fn focusDistance(&self) -> f64 {
+        return self.focusDistance;
+    }
+
+    pub fn bladeCount(&self) -> usize {
+        return self.bladeCount;
+    }
+
+    pub fn bladeRotation(&self) -> f64 {
+        return self.bladeRotation;
+    }
+
+    pub fn metersPerUnit(&self) -> f64 {
+        return self.metersPerUnit;
+    }
+
+    // 垂直方向的视角，弧度
+    pub fn fov(&self) -> f64 {
+        return self.fov;
+    }
+
+    pub fn aspect(&self) -> f64 {
+        return self.sensorWidth / self.sensorHeight;
+    }
+
+    // 场景单位
+    pub fn apertureRadius(&self) -> f64 {
+        return self.apertureRadius;
+    }
+}
+
+impl Camera for ThinLensCamera {
+    fn ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Ray {
+        let target = self.lowerLeft + self.horizontal * u + self.vertical * v;
+        if self.apertureRadius == 0.0 {
+            return Ray::new(self.eye, (target - self.eye).normalized());
+        }
+
+        let rd = if self.bladeCount >= 3 {
+            randomInRegularPolygon(self.bladeCount, self.bladeRotation, sampler)
+        } else {
+            randomInUnitDisk(sampler)
+        } * self.apertureRadius;
+        let offset = self.horizontal.normalized() * rd.x() + self.vertical.normalized() * rd.y();
+        return Ray::new(self.eye + offset, (target - self.eye - offset).normalized());
+    }
+}
+
+// 镜头设计表里的一行，一个球面。数据一般是从专利或者镜头设计的书上抄下来的，单位都是毫米
+// 从物体那边往胶片那边排，和设计表的顺序一样
+#[derive(Copy, Clone, Debug)]
+pub struct LensElement {
+    curvatureRadius: f64, // 曲率中心在胶片那边（凸向物体）是正的，0是平面，光圈也是平面
+    thickness: f64,       // 到下一个面的距离，最后一个面的不用，胶片的位置是对焦算出来的
+    ior: f64,             // 这个面和下一个面之间的折射率，空气是1
+    apertureDiameter: f64, // 这个面的通光直径，超出去的光线被挡住
+}
+
+impl LensElement {
+    pub fn new(curvatureRadius: f64, thickness: f64, ior: f64, apertureDiameter: f64) -> Self {
+        Self {
+            curvatureRadius: curvatureRadius,
+            thickness: thickness,
+            ior: ior,
+            apertureDiameter: apertureDiameter,
+        }
+    }
+
+    pub fn curvatureRadius(&self) -> f64 {
+        return self.curvatureRadius;
+    }
+
+    pub fn thickness(&self) -> f64 {
+        return self.thickness;
+    }
+
+    pub fn ior(&self) -> f64 {
+        return self.ior;
+    }
+
+    pub fn apertureDiameter(&self) -> f64 {
+        return self.apertureDiameter;
+    }
+
+    // 50mm f/2的双高斯镜头，US patent 2,673,491，pbrt带的那个dgauss.50mm
+    pub fn doubleGauss50mm() -> Vec<LensElement> {
+        return vec![
+            LensElement::new(29.475, 3.76, 1.67, 25.2),
+            LensElement::new(84.83, 0.12, 1.0, 25.2),
+            LensElement::new(19.275, 4.025, 1.67, 23.0),
+            LensElement::new(40.77, 3.275, 1.699, 23.0),
+            LensElement::new(12.75, 5.705, 1.0, 18.0),
+            LensElement::new(0.0, 4.5, 1.0, 17.1), // 光圈
+            LensElement::new(-14.495, 1.18, 1.603, 17.0),
+            LensElement::new(40.77, 6.065, 1.658, 20.0),
+            LensElement::new(-20.385, 0.19, 1.0, 20.0),
+            LensElement::new(437.065, 3.22, 1.717, 20.0),
+            LensElement::new(-39.73, 5.0, 1.0, 20.0),
+        ];
+    }
+}
+
+// 真实的多片镜头相机，光线从胶片出发一个面一个面地折射出去，和pbrt的RealisticCamera一样
+// <https://www.pbr-book.org/3ed-2018/Camera_Models/Realistic_Cameras>
+// 不用另外模拟，镜头本身就会带来这些效果：
+// - 渐晕：斜着的光线会被镜筒（各个面的通光直径）挡住，再加上cos^4的衰减
+// - 畸变：画面边缘的光线折射得和中间不一样
+// - 呼吸效应：对焦是前后移动胶片，对焦越近胶片离镜头越远，视角越小
+// 镜头坐标系里胶片在z = 0，镜头在+z那边，单位是毫米；eye是最前面那个面的顶点
+// 只从最后一个面上均匀取点，很多光线会被挡住，所以比别的相机噪点多一些
+#[derive(Clone, Debug)]
+pub struct RealisticCamera {
+    eye: Vec3,
+    center: Vec3,
+    up: Vec3,
+    elements: Vec<LensElement>,
+    sensorWidth: f64,   // 毫米
+    sensorHeight: f64,  // 毫米
+    focusDistance: f64, // 场景单位，从最前面的面算起
+    metersPerUnit: f64,
+    frame: Frame,
+    positions: Vec<f64>, // 每个面的顶点离最后一个面有多远
+    filmDistance: f64,   // 胶片离最后一个面有多远
+    exposure: f64,       // 画面中心平均有多少光线能出去，用来把中心的权重归一化成1
+}
+
+pub struct RealisticCameraBuilder {
+    camera: RealisticCamera,
+}
+
+impl RealisticCameraBuilder {
+    // 没有镜片、对不上焦（比如比最近对焦距离还近）或者光线到不了胶片中心的话返回InvalidInput
+    pub fn build(mut self) -> Result<RealisticCamera> {
+        self.camera.update()?;
+        return Ok(self.camera);
+    }
+
+    pub fn sensor(mut self, width: f64, height: f64) -> Self {
+        self.camera.sensorWidth = width;
+        self.camera.sensorHeight = height;
+        return self;
+    }
+
+    pub fn focusDistance(mut self, focusDistance: f64) -> Self {
+        self.camera.focusDistance = focusDistance;
+        return self;
+    }
+
+    pub fn metersPerUnit(mut self, metersPerUnit: f64) -> Self {
+        self.camera.metersPerUnit = metersPerUnit;
+        return self;
+    }
+
+    // 改光圈（第一个前后都是空气的平面）的直径，收光圈用
+    pub fn stopDiameter(mut self, stopDiameter: f64) -> Self {
+        let elements = &mut self.camera.elements;
+        for i in 0..elements.len() {
+            let front = if i == 0 { 1.0 } else { elements[i - 1].ior };
+            if elements[i].curvatureRadius == 0.0 && elements[i].ior == 1.0 && front == 1.0 {
+                elements[i].apertureDiameter = stopDiameter;
+                break;
+            }
+        }
+        return self;
+    }
+}
+
+impl RealisticCamera {
+    // 默认全画幅，对焦在center上
+    pub fn builder(
+        eye: Vec3,
+        center: Vec3,
+        up: Vec3,
+        elements: Vec<LensElement>,
+    ) -> RealisticCameraBuilder {
+        RealisticCameraBuilder {
+            camera: RealisticCamera {
+                eye: eye,
+                center: center,
+                up: up,
+                elements: elements,
+                sensorWidth: 36.0,
+                sensorHeight: 24.0,
+                focusDistance: (center - eye).length(),
+                metersPerUnit: 1.0,
+                frame: Frame::new(&eye, &center, &up),
+                positions: vec![],
+                filmDistance: 0.0,
+                exposure: 1.0,
+            },
+        }
+    }
+
+    // 算每个面的位置，再对焦、算中心的曝光
+    fn update(&mut self) -> Result<()> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidInput, message.to_string());
+        if self.elements.is_empty() {
+            return Err(invalid("lens needs an element"));
+        }
+
+        let count = self.elements.len();
+        self.positions = vec![0.0; count];
+        for i in (0..count - 1).rev() {
+            self.positions[i] = self.positions[i + 1] + self.elements[i].thickness;
+        }
+
+        // 从对焦平面上光轴上的一点往最前面的面上靠近光轴的地方射一条光线，出来以后和光轴的交点就是胶片的位置
+        self.filmDistance = 0.0;
+        let front = self.positions[0];
+        let object = Vec3::new(
+            0.0,
+            0.0,
+            front + self.focusDistance * self.metersPerUnit * 1000.0,
+        );
+        let height = self.elements[0].apertureDiameter / 2.0 * 0.01;
+        let target = Vec3::new(height, 0.0, front);
+        // 对焦在很远的地方的话，从物体那里开始求交浮点数精度不够，挪到镜头前面一点再开始
+        let direction = (target - object).normalized();
+        let start = target - direction * self.elements[0].apertureDiameter;
+        let image = self
+            .traceFromScene(start, direction)
+            .and_then(|(origin, direction)| {
+                if direction.x() >= 0.0 {
+                    return None;
+                }
+                return Some(origin.z() - origin.x() / direction.x() * direction.z());
+            });
+        self.filmDistance = match image {
+            Some(z) if z < 0.0 => -z,
+            _ => return Err(invalid("lens cannot focus at this distance")),
+        };
+
+        // 从胶片中心往最后一个面上均匀地射一些光线
+        let rearRadius = self.elements[count - 1].apertureDiameter / 2.0;
+        let n = 32;
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let r = rearRadius * ((i as f64 + 0.5) / n as f64).sqrt();
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n as f64;
+                let point = Vec3::new(r * phi.cos(), r * phi.sin(), self.filmDistance);
+                let direction = point.normalized();
+                if self
+                    .traceFromFilm(Vec3::new(0.0, 0.0, 0.0), direction)
+                    .is_some()
+                {
+                    sum += direction.z().powi(4);
+                }
+            }
+        }
+        if sum <= 0.0 {
+            return Err(invalid("no light reaches the film center"));
+        }
+        self.exposure = sum / (n * n) as f64;
+        return Ok(());
+    }
+
+    // 光线和第i个面的交点和这一点的法向量（平面是None），超出通光直径就是None
+    fn intersect(&self, i: usize, origin: &Vec3, direction: &Vec3) -> Option<(Vec3, Option<Vec3>)> {
+        let element = &self.elements[i];
+        let vertex = self.positions[i] + self.filmDistance;
+        let radius = element.curvatureRadius;
+
+        let (point, normal) = if radius == 0.0 {
+            let t = (vertex - origin.z()) / direction.z();
+            if t.is_nan() || t <= 0.0 {
+                return None;
+            }
+            (*origin + *direction * t, None)
+        } else {
+            // 球面只要顶点那一侧的半个
+            let center = Vec3::new(0.0, 0.0, vertex - radius);
+            let oc = *origin - center;
+            let b = oc.dot(direction);
+            let discriminant = b * b - (oc.dot(&oc) - radius * radius);
+            if discriminant < 0.0 {
+                return None;
+            }
+            let root = discriminant.sqrt();
+            let point = [-b - root, -b + root]
+                .iter()
+                .filter(|t| **t > 1e-9)
+                .map(|t| *origin + *direction * *t)
+                .find(|p| (p.z() - center.z()) * radius > 0.0)?;
+            (point, Some((point - center).normalized()))
+        };
+
+        let apertureRadius = element.apertureDiameter / 2.0;
+        if point.x() * point.x() + point.y() * point.y() > apertureRadius * apertureRadius {
+            return None;
+        }
+        return Some((point, normal));
+    }
+
+    // 从n1进到n2，normal朝哪边都行
+    fn refract(direction: &Vec3, normal: Option<Vec3>, n1: f64, n2: f64) -> Option<Vec3> {
+        match normal {
+            Some(normal) => {
+                let normal = if normal.dot(direction) > 0.0 {
+                    -normal
+                } else {
+                    normal
+                };
+                return Some(direction.refracted(&normal, n1 / n2)?.normalized());
+            }
+            None => return Some(*direction),
+        }
+    }
+
+    // 都是镜头坐标系，direction要是单位向量，返回出去以后的起点和方向，被挡住了或者全反射了就是None
+    fn traceFromFilm(&self, mut origin: Vec3, mut direction: Vec3) -> Option<(Vec3, Vec3)> {
+        for i in (0..self.elements.len()).rev() {
+            let (point, normal) = self.intersect(i, &origin, &direction)?;
+            let outside = if i == 0 {
+                1.0
+            } else {
+                self.elements[i - 1].ior
+            };
+            direction = Self::refract(&direction, normal, self.elements[i].ior, outside)?;
+            origin = point;
+        }
+        return Some((origin, direction));
+    }
+
+    fn traceFromScene(&self, mut origin: Vec3, mut direction: Vec3) -> Option<(Vec3, Vec3)> {
+        for i in 0..self.elements.len() {
+            let (point, normal) = self.intersect(i, &origin, &direction)?;
+            let outside = if i == 0 {
+                1.0
+            } else {
+                self.elements[i - 1].ior
+            };
+            direction = Self::refract(&direction, normal, outside, self.elements[i].ior)?;
+            origin = point;
+        }
+        return Some((origin, direction));
+    }
+
+    pub fn eye(&self) -> &Vec3 {
+        return &self.eye;
+    }
+
+    pub fn center(&self) -> &Vec3 {
+        return &self.center;
+    }
+
+    pub fn up(&self) -> &Vec3 {
+        return &self.up;
+    }
+
+    pub fn elements(&self) -> &Vec<LensElement> {
+        return &self.elements;
+    }
+
+    pub fn sensorWidth(&self) -> f64 {
+        return self.sensorWidth;
+    }
+
+    pub fn sensorHeight(&self) -> f64 {
+        return self.sensorHeight;
+    }
+
+    pub fn focusDistance(&self) -> f64 {
+        return self.focusDistance;
+    }
+
+    pub fn metersPerUnit(&self) -> f64 {
+        return self.metersPerUnit;
+    }
+
+    // 胶片离最后一个面的距离，毫米。对焦越近越大
+    pub fn filmDistance(&self) -> f64 {
+        return self.filmDistance;
+    }
+}
+
+impl Camera for RealisticCamera {
+    // 不管权重，挡住了就换一个镜头上的点重新射，所以没有渐晕。试了很多次都挡住了就从中间直接往前射
+    fn ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Ray {
+        for _ in 0..64 {
+            if let Some((ray, _)) = self.weightedRay(u, v, sampler) {
+                return ray;
+            }
+        }
+        return Ray::new(self.eye, self.frame.forward);
+    }
+
+    fn weightedRay(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<(Ray, f64)> {
+        // 镜头成的像是倒着的，画面右上角在胶片的左下角
+        let film = Vec3::new(
+            (0.5 - u) * self.sensorWidth,
+            (0.5 - v) * self.sensorHeight,
+            0.0,
+        );
+        let rear = randomInUnitDisk(sampler)
+            * (self.elements[self.elements.len() - 1].apertureDiameter / 2.0)
+            + Vec3::new(0.0, 0.0, self.filmDistance);
+        let direction = (rear - film).normalized();
+        let (origin, outgoing) = self.traceFromFilm(film, direction)?;
+
+        // 镜头坐标系换到世界坐标系，原点放到最前面的面的顶点上
+        let scale = 1.0 / (self.metersPerUnit * 1000.0);
+        let front = self.positions[0] + self.filmDistance;
+        let frame = &self.frame;
+        let toWorld = |v: &Vec3| -> Vec3 {
+            return frame.right * v.x() + frame.up * v.y() + frame.forward * v.z();
+        };
+        let origin = self.eye + toWorld(&(origin - Vec3::new(0.0, 0.0, front))) * scale;
+        let ray = Ray::new(origin, toWorld(&outgoing).normalized());
+        return Some((ray, direction.z().powi(4) / self.exposure));
+    }
+}
+
+// 正交相机，光线都是平行的，远近的物体一样大，画建筑图、工程图用
+// 光线从eye所在的、垂直于视线的平面上出发，这个平面上width x height的一块就是画面
+// 所有光线都从同一个平面出发，没有镜头，所以没有景深
+#[derive(Clone, Debug)]
+pub struct OrthographicCamera {
+    eye: Vec3,
+    center: Vec3,
+    up: Vec3,
+    width: f64,  // 画面在世界坐标系里有多宽
+    height: f64, // 画面在世界坐标系里有多高
+    lowerLeft: Vec3,
+    horizontal: Vec3,
+    vertical: Vec3,
+    direction: Vec3,
+}
+
+impl OrthographicCamera {
+    pub fn new(eye: Vec3, center: Vec3, up: Vec3, width: f64, height: f64) -> Self {
+        let up = up.normalized();
+        let w = (eye - center).normalized();
+        let u = up.cross(&w).normalized();
+        let v = w.cross(&u);
+
+        let horizontal = u * width;
+        let vertical = v * height;
+        let lowerLeft = eye - horizontal / 2.0 - vertical / 2.0;
+
+        Self {
+            eye: eye,
+            center: center,
+            up: up,
+            width: width,
+            height: height,
+            lowerLeft: lowerLeft,
+            horizontal: horizontal,
+            vertical: vertical,
+            direction: -w,
+        }
+    }
+
+    pub fn eye(&self) -> &Vec3 {
+        return &self.eye;
+    }
+
+    pub fn center(&self) -> &Vec3 {
+        return &self.center;
+    }
+
+    pub fn up(&self) -> &Vec3 {
+        return &self.up;
+    }
+
+    pub fn width(&self) -> f64 {
+        return self.width;
+    }
+
+    pub fn height(&self) -> f64 {
+        return self.height;
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn ray(&self, u: f64, v: f64, _sampler: &mut dyn Sampler) -> Ray {
+        return Ray::new(
+            self.lowerLeft + self.horizontal * u + self.vertical * v,
+            self.direction,
+        );
+    }
+}
+
+// 全景相机都用这个坐标系：forward是看的方向，up是上方，right = forward x up，和PerspectiveCamera画面的右边一样
+#[derive(Copy, Clone, Debug)]
+struct Frame {
+    right: Vec3,
+    up: Vec3,
+    forward: Vec3,
+}
+
+impl Frame {
+    fn new(eye: &Vec3, center: &Vec3, up: &Vec3) -> Self {
+        let forward = (*center - *eye).normalized();
+        let right = forward.cross(up).normalized();
+        Self {
+            right: right,
+            up: right.cross(&forward),
+            forward: forward,
+        }
+    }
+}
+
+// 经纬度展开的全景相机，u绕一圈360度，v从正下方到正上方，画面宽高比应该是2:1
+// 展开的方式和EquirectangularEnvironment一样：center = eye + ez、up = ey的时候，渲染出来的图可以直接当环境贴图用
+// 所以从里面看的时候左右是反的（u变大是从forward往left转），和球的贴图一个道理
+#[derive(Clone, Debug)]
+pub struct EquirectangularCamera {
+    eye: Vec3,
+    center: Vec3,
+    up: Vec3,
+    frame: Frame,
+}
+
+impl EquirectangularCamera {
+    pub fn new(eye: Vec3, center: Vec3, up: Vec3) -> Self {
+        Self {
+            eye: eye,
+            center: center,
+            up: up,
+            frame: Frame::new(&eye, &center, &up),
+        }
+    }
+
+    pub fn eye(&self) -> &Vec3 {
+        return &self.eye;
+    }
+
+    pub fn center(&self) -> &Vec3 {
+        return &self.center;
+    }
+
+    pub fn up(&self) -> &Vec3 {
+        return &self.up;
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn ray(&self, u: f64, v: f64, _sampler: &mut dyn Sampler) -> Ray {
+        // 环境贴图坐标系里x是left、y是up、z是forward
+        let local = EquirectangularEnvironment::uvToDirection(&(u, v));
+        let frame = &self.frame;
+        let direction = -frame.right * local.x() + frame.up * local.y() + frame.forward * local.z();
+        return Ray::new(self.eye, direction.normalized());
+    }
+}
+
+// 立方体贴图相机，六个面从左到右排成一行：+x、-x、+y、-y、+z、-z，画面宽高比应该是6:1
+// 每个面的朝向和OpenGL的cube map一样，面的第一行是画面的最上面一行，所以导出的图可以直接切成六张贴图用
+// 立方体贴图都是对齐世界坐标轴的，所以只有位置，没有朝向
+#[derive(Clone, Debug)]
+pub struct CubeMapCamera {
+    eye: Vec3,
+}
+
+impl CubeMapCamera {
+    pub fn new(eye: Vec3) -> Self {
+        Self { eye: eye }
+    }
+
+    pub fn eye(&self) -> &Vec3 {
+        return &self.eye;
+    }
+
+    // 第face个面上的(s, t)对应的方向，s从左往右，t从上往下，都是[-1, 1]
+    // <https://www.khronos.org/opengl/wiki/Cubemap_Texture>
+    pub fn faceDirection(face: usize, s: f64, t: f64) -> Vec3 {
+        match face {
+            0 => Vec3::new(1.0, -t, -s),
+            1 => Vec3::new(-1.0, -t, s),
+            2 => Vec3::new(s, 1.0, t),
+            3 => Vec3::new(s, -1.0, -t),
+            4 => Vec3::new(s, -t, 1.0),
+            5 => Vec3::new(-s, -t, -1.0),
+            v => panic!("face out of range: {}", v),
+        }
+    }
+}
+
+impl Camera for CubeMapCamera {
+    fn ray(&self, u: f64, v: f64, _sampler: &mut dyn Sampler) -> Ray {
+        let position = u * 6.0;
+        let face = (position.floor() as usize).min(5);
+        let s = (position - face as f64) * 2.0 - 1.0;
+        let t = (1.0 - v) * 2.0 - 1.0;
+        return Ray::new(self.eye, Self::faceDirection(face, s, t).normalized());
+    }
+}
+
+// 等距（equidistant）鱼眼相机，离画面中心的距离和光线与视线的夹角成正比
+// fov是画面宽度方向上的视角，可以超过180度，最大360度；aspect是画面宽高比
+// 画面中心的圆以外也有光线，是接着往外的方向，要圆形的画面（比如球幕用的）就把fov设成180度、画面设成正方形，再把圆外面涂黑
+#[derive(Clone, Debug)]
+pub struct FisheyeCamera {
+    eye: Vec3,
+    center: Vec3,
+    up: Vec3,
+    fov: f64,
+    aspect: f64,
+    frame: Frame,
+}
+
+impl FisheyeCamera {
+    pub fn new(eye: Vec3, center: Vec3, up: Vec3, fov: f64, aspect: f64) -> Self {
+        Self {
+            eye: eye,
+            center: center,
+            up: up,
+            fov: fov.min(2.0 * PI),
+            aspect: aspect,
+            frame: Frame::new(&eye, &center, &up),
+        }
+    }
+
+    pub fn eye(&self) -> &Vec3 {
+        return &self.eye;
+    }
+
+    pub fn center(&self) -> &Vec3 {
+        return &self.center;
+    }
+
+    pub fn up(&self) -> &Vec3 {
+        return &self.up;
+    }
+
+    pub fn fov(&self) -> f64 {
+        return self.fov;
+    }
+
+    pub fn aspect(&self) -> f64 {
+        return self.aspect;
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn ray(&self, u: f64, v: f64, _sampler: &mut dyn Sampler) -> Ray {
+        // 画面左右两边是[-1, 1]，上下按宽高比缩一下，这样pixel是正方形的
+        let x = u * 2.0 - 1.0;
+        let y = (v * 2.0 - 1.0) / self.aspect;
+        let r = (x * x + y * y).sqrt();
+        let theta = (r * self.fov / 2.0).min(PI);
+        let phi = y.atan2(x);
+
+        let frame = &self.frame;
+        let direction = frame.right * theta.sin() * phi.cos()
+            + frame.up * theta.sin() * phi.sin()
+            + frame.forward * theta.cos();
+        return Ray::new(self.eye, direction.normalized());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::camera::Camera;
+    use crate::camera::CubeMapCamera;
+    use crate::camera::EquirectangularCamera;
+    use crate::camera::FisheyeCamera;
+    use crate::camera::LensElement;
+    use crate::camera::OrthographicCamera;
+    use crate::camera::PerspectiveCamera;
+    use crate::camera::RealisticCamera;
+    use crate::camera::ShutterCamera;
+    use crate::camera::ThinLensCamera;
+    use crate::environment::EquirectangularEnvironment;
+    use crate::sampler::RandomSampler;
+    use crate::vec3::Vec3;
+
+    use std::f64::consts::PI;
+    use std::io::ErrorKind;
+
+    // 所有光线方向一样，起点铺满width x height的画面
+    #[test]
+    fn orthographicRaysAreParallel() {
+        let camera = OrthographicCamera::new(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::ey(),
+            4.0,
+            2.0,
+        );
+        let mut sampler = RandomSampler::new(0);
+
+        let center = camera.ray(0.5, 0.5, &mut sampler);
+        assert!((*center.origin() - Vec3::new(0.0, 0.0, 5.0)).length() < 1e-12);
+        assert!((*center.direction() - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-12);
+
+        let corner = camera.ray(1.0, 0.0, &mut sampler);
+        assert!((*corner.origin() - Vec3::new(2.0, -1.0, 5.0)).length() < 1e-12);
+        assert_eq!(corner.direction(), center.direction());
+    }
+
+    // 世界坐标系朝向的经纬度相机和EquirectangularEnvironment的展开方式一样
+    #[test]
+    fn equirectangularMatchesEnvironment() {
+        let camera = EquirectangularCamera::new(
+            Vec3::new(1.0, 2.0, 3.0),
+            Vec3::new(1.0, 2.0, 4.0),
+            Vec3::ey(),
+        );
+        let mut sampler = RandomSampler::new(0);
+
+        for (u, v) in [(0.5, 0.5), (0.1, 0.3), (0.75, 0.9), (0.3, 0.05)].iter() {
+            let ray = camera.ray(*u, *v, &mut sampler);
+            assert_eq!(*ray.origin(), Vec3::new(1.0, 2.0, 3.0));
+            let uv = EquirectangularEnvironment::directionToUv(ray.direction());
+            assert!((uv.0 - u).abs() < 1e-9 && (uv.1 - v).abs() < 1e-9);
+        }
+    }
+
+    // 每个面的中心对着坐标轴，+z面的右上角是(1, 1, 1)方向
+    #[test]
+    fn cubeMapFacesPointAlongAxes() {
+        let camera = CubeMapCamera::new(Vec3::new(0.0, 0.0, 0.0));
+        let mut sampler = RandomSampler::new(0);
+        let axes = [
+            Vec3::ex(),
+            -Vec3::ex(),
+            Vec3::ey(),
+            -Vec3::ey(),
+            Vec3::ez(),
+            -Vec3::ez(),
+        ];
+
+        for (face, axis) in axes.iter().enumerate() {
+            let ray = camera.ray((face as f64 + 0.5) / 6.0, 0.5, &mut sampler);
+            assert!((*ray.direction() - *axis).length() < 1e-12);
+        }
+
+        let corner = camera.ray(5.0 / 6.0 - 1e-9, 1.0, &mut sampler);
+        let expected = Vec3::new(1.0, 1.0, 1.0).normalized();
+        assert!((*corner.direction() - expected).length() < 1e-6);
+    }
+
+    // 画面中心对着center，左右边缘和视线的夹角是fov的一半
+    #[test]
+    fn fisheyeAngleGrowsLinearly() {
+        let camera = FisheyeCamera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::ey(),
+            PI,
+            1.0,
+        );
+        let mut sampler = RandomSampler::new(0);
+        let forward = Vec3::new(0.0, 0.0, -1.0);
+
+        let center = camera.ray(0.5, 0.5, &mut sampler);
+        assert!((*center.direction() - forward).length() < 1e-12);
+
+        let right = camera.ray(1.0, 0.5, &mut sampler);
+        assert!((*right.direction() - Vec3::ex()).length() < 1e-12);
+
+        let top = camera.ray(0.5, 0.75, &mut sampler);
+        let angle = top.direction().dot(&forward).acos();
+        assert!((angle - PI / 4.0).abs() < 1e-12);
+        assert!(top.direction().y() > 0.0);
+    }
+
+    // 镜头上的点在过eye、垂直于视线的圆盘里，沿着画面的水平和竖直方向都有偏移
+    #[test]
+    fn perspectiveLensLiesAcrossView() {
+        let camera = PerspectiveCamera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            -Vec3::ez(),
+            Vec3::ey(),
+            1.0,
+            1.0,
+            1.0,
+            0.1,
+        );
+        let mut sampler = RandomSampler::new(0);
+        let (mut maxX, mut maxY): (f64, f64) = (0.0, 0.0);
+        for _ in 0..1000 {
+            let origin = *camera.ray(0.3, 0.8, &mut sampler).origin();
+            assert!(origin.z().abs() < 1e-12);
+            assert!(origin.length() <= 0.1 + 1e-12);
+            maxX = maxX.max(origin.x().abs());
+            maxY = maxY.max(origin.y().abs());
+        }
+        assert!(maxX > 0.09 && maxY > 0.09);
+    }
+
+    // 光线的时间在快门开关之间，而且两头都能取到附近；没设快门的话都是0。哪种相机都一样
+    #[test]
+    fn shutterTimesCoverInterval() {
+        let camera = PerspectiveCamera::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            -Vec3::ez(),
+            Vec3::ey(),
+            1.0,
+            1.0,
+            1.0,
+            0.1,
+        );
+        let mut sampler = RandomSampler::new(0);
+        assert_eq!(camera.ray(0.5, 0.5, &mut sampler).time(), 0.0);
+
+        let camera = ShutterCamera::new(camera, 0.25, 0.75);
+        assert_eq!(camera.shutter(), (0.25, 0.75));
+        let times: Vec<f64> = (0..1000)
+            .map(|_| camera.ray(0.5, 0.5, &mut sampler).time())
+            .collect();
+        assert!(times.iter().all(|v| *v >= 0.25 && *v <= 0.75));
+        assert!(times.iter().any(|v| *v < 0.3) && times.iter().any(|v| *v > 0.7));
+
+        let camera = ShutterCamera::new(CubeMapCamera::new(Vec3::new(0.0, 0.0, 0.0)), 1.0, 2.0);
+        let (ray, weight) = camera.weightedRay(0.5, 0.5, &mut sampler).unwrap();
+        assert!(ray.time() >= 1.0 && ray.time() <= 2.0);
+        assert_eq!(weight, 1.0);
+    }
+
+    // 全画幅50mm f/2对焦在无穷远附近：视角是2 * atan(12 / 50)，光圈半径12.5mm
+    // 4片叶片、第一片在x轴上的时候光圈是|x| + |y| <= r的菱形，所有光线都会聚到对焦平面上的同一点
+    #[test]
+    fn thinLensMatchesPhotographicParameters() {
+        let camera = ThinLensCamera::builder(Vec3::new(0.0, 0.0, 0.0), -Vec3::ez(), Vec3::ey())
+            .fNumber(2.0)
+            .focusDistance(1e6)
+            .build()
+            .unwrap();
+        assert!((camera.fov() - 2.0 * (12.0 as f64 / 50.0).atan()).abs() < 1e-6);
+        assert!((camera.apertureRadius() - 0.0125).abs() < 1e-12);
+        assert!((camera.aspect() - 1.5).abs() < 1e-12);
+
+        let camera = ThinLensCamera::builder(Vec3::new(0.0, 0.0, 0.0), -Vec3::ez(), Vec3::ey())
+            .fNumber(2.0)
+            .focusDistance(2.0)
+            .blades(4, 0.0)
+            .build()
+            .unwrap();
+        // 对焦近了，传感器离镜头远了，视角变小
+        assert!(camera.fov() < 2.0 * (12.0 as f64 / 50.0).atan());
+
+        let mut sampler = RandomSampler::new(0);
+        let r = camera.apertureRadius();
+        let mut maxX: f64 = 0.0;
+        for _ in 0..1000 {
+            let ray = camera.ray(0.7, 0.4, &mut sampler);
+            let origin = *ray.origin();
+            assert!(origin.x().abs() + origin.y().abs() <= r * (1.0 + 1e-9));
+            maxX = maxX.max(origin.x().abs());
+
+            let t = 2.0 / -ray.direction().z();
+            let focused = origin + *ray.direction() * t;
+            let expected = camera.ray(0.7, 0.4, &mut sampler);
+            let expected =
+                *expected.origin() + *expected.direction() * (2.0 / -expected.direction().z());
+            assert!((focused - expected).length() < 1e-9);
+        }
+        assert!(maxX > 0.9 * r);
+
+        // 比焦距还近、eye和center重合的时候对不上焦
+        let builder = || ThinLensCamera::builder(Vec3::new(0.0, 0.0, 0.0), -Vec3::ez(), Vec3::ey());
+        let error = builder().focusDistance(0.04).build().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        let error = ThinLensCamera::builder(Vec3::ez(), Vec3::ez(), Vec3::ey())
+            .build()
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(builder().focusDistance(0.06).build().is_ok());
+    }
+
+    // 双高斯50mm：对焦在无穷远的时候胶片在后焦距那里，对焦近了胶片往后挪
+    // 画面中心的光线会聚到对焦平面上，平均权重是1；角上的光线很多被挡住，暗很多
+    #[test]
+    fn realisticCameraFocusesAndVignettes() {
+        let camera = |focusDistance: f64| -> RealisticCamera {
+            return RealisticCamera::builder(
+                Vec3::new(0.0, 0.0, 0.0),
+                -Vec3::ez(),
+                Vec3::ey(),
+                LensElement::doubleGauss50mm(),
+            )
+            .focusDistance(focusDistance)
+            .build()
+            .unwrap();
+        };
+        assert!((camera(1e6).filmDistance() - 36.1).abs() < 0.05);
+        assert!(camera(0.5).filmDistance() > camera(1e6).filmDistance() + 5.0);
+
+        let camera = camera(2.0);
+        let mut sampler = RandomSampler::new(0);
+        let count = 4000;
+        let mut measure = |u: f64, v: f64| -> (f64, f64) {
+            let mut weight = 0.0;
+            let mut points = vec![];
+            for _ in 0..count {
+                if let Some((ray, w)) = camera.weightedRay(u, v, &mut sampler) {
+                    weight += w;
+                    points.push(*ray.origin() + *ray.direction() * (-2.0 / ray.direction().z()));
+                }
+            }
+            let spread = points
+                .iter()
+                .map(|v| (*v - points[0]).length())
+                .fold(0.0, f64::max);
+            return (weight / count as f64, spread);
+        };
+
+        let (centerWeight, centerSpread) = measure(0.5, 0.5);
+        assert!((centerWeight - 1.0).abs() < 0.05);
+        assert!(centerSpread < 0.005);
+        let (cornerWeight, _) = measure(1.0, 1.0);
+        assert!(cornerWeight < 0.5 * centerWeight);
+
+        // 没有镜片、光圈关死了、比最近对焦距离还近，都不能用
+        let builder = |elements: Vec<LensElement>| {
+            return RealisticCamera::builder(
+                Vec3::new(0.0, 0.0, 0.0),
+                -Vec3::ez(),
+                Vec3::ey(),
+                elements,
+            );
+        };
+        let errors = [
+            builder(vec![]).build().unwrap_err(),
+            builder(LensElement::doubleGauss50mm())
+                .stopDiameter(0.0)
+                .build()
+                .unwrap_err(),
+            builder(LensElement::doubleGauss50mm())
+                .focusDistance(0.01)
+                .build()
+                .unwrap_err(),
+        ];
+        assert!(errors
+            .iter()
+            .all(|error| error.kind() == ErrorKind::InvalidInput));
+    }
+}
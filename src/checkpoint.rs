@@ -0,0 +1,494 @@
+use crate::film::Film;
+use crate::ray::Hit;
+use crate::render::Accumulation;
+use crate::render::PixelStatistics;
+use crate::render::Progress;
+use crate::render::Renderer;
+use crate::render::Splats;
+use crate::render::Tile;
+use crate::render::TileResult;
+use crate::vec3::Vec3;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Result;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use std::time::Instant;
+
+// checkpoint文件的格式：开头是magic和fingerprint()，然后是整个画面的Accumulation，最后是这一遍里已经渲染完的tile
+// 分布式渲染的时候worker发回来的tile也是同样的格式
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RTCKPT03";
+
+// checkpoint文件和分布式渲染的连接上的数都是little endian，浮点数按位存，读回来和原来一模一样
+pub(crate) fn writeU64(writer: &mut dyn Write, value: u64) -> Result<()> {
+    return writer.write_all(&value.to_le_bytes());
+}
+
+fn writeF64(writer: &mut dyn Write, value: f64) -> Result<()> {
+    return writer.write_all(&value.to_le_bytes());
+}
+
+fn writeVec3(writer: &mut dyn Write, value: &Vec3) -> Result<()> {
+    writeF64(writer, value.x())?;
+    writeF64(writer, value.y())?;
+    return writeF64(writer, value.z());
+}
+
+pub(crate) fn readU64(reader: &mut dyn Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    return Ok(u64::from_le_bytes(bytes));
+}
+
+fn readF64(reader: &mut dyn Read) -> Result<f64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    return Ok(f64::from_le_bytes(bytes));
+}
+
+fn readVec3(reader: &mut dyn Read) -> Result<Vec3> {
+    let x = readF64(reader)?;
+    let y = readF64(reader)?;
+    let z = readF64(reader)?;
+    return Ok(Vec3::new(x, y, z));
+}
+
+fn writeStatistics(writer: &mut dyn Write, statistics: &[Vec<PixelStatistics>]) -> Result<()> {
+    for pixel in statistics.iter().flatten() {
+        writeVec3(writer, &pixel.sum)?;
+        writeU64(writer, pixel.sampleCount as u64)?;
+        writeF64(writer, pixel.mean)?;
+        writeF64(writer, pixel.m2)?;
+        writeU64(writer, pixel.unblockedCount as u64)?;
+    }
+    return Ok(());
+}
+
+fn readStatistics(
+    reader: &mut dyn Read,
+    width: usize,
+    height: usize,
+) -> Result<Vec<Vec<PixelStatistics>>> {
+    let mut res = vec![vec![PixelStatistics::new(); width]; height];
+    for pixel in res.iter_mut().flatten() {
+        pixel.sum = readVec3(reader)?;
+        pixel.sampleCount = readU64(reader)? as usize;
+        pixel.mean = readF64(reader)?;
+        pixel.m2 = readF64(reader)?;
+        pixel.unblockedCount = readU64(reader)? as usize;
+        if pixel.unblockedCount > pixel.sampleCount {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "more unblocked samples than samples",
+            ));
+        }
+    }
+    return Ok(res);
+}
+
+// FNV-1a。DefaultHasher不保证不同版本的Rust算出来一样，而coordinator和worker可能是分别编译的
+fn hashString(value: &str) -> u64 {
+    let mut res: u64 = 0xcbf29ce484222325;
+    for byte in value.bytes() {
+        res ^= byte as u64;
+        res = res.wrapping_mul(0x100000001b3);
+    }
+    return res;
+}
+
+// 颜色之和、权重之和、每种AOV之和，Accumulation和Splats都是这几样
+fn writeSums(
+    writer: &mut dyn Write,
+    sums: &[Vec<Vec3>],
+    weights: &[Vec<f64>],
+    aovs: &[Vec<Vec<Vec3>>],
+) -> Result<()> {
+    for sum in sums.iter().flatten() {
+        writeVec3(writer, sum)?;
+    }
+    for weight in weights.iter().flatten() {
+        writeF64(writer, *weight)?;
+    }
+    for sum in aovs.iter().flatten().flatten() {
+        writeVec3(writer, sum)?;
+    }
+    return Ok(());
+}
+
+#[allow(clippy::type_complexity)]
+fn readSums(
+    reader: &mut dyn Read,
+    width: usize,
+    height: usize,
+    aovCount: usize,
+) -> Result<(Vec<Vec<Vec3>>, Vec<Vec<f64>>, Vec<Vec<Vec<Vec3>>>)> {
+    let mut sums = vec![vec![Vec3::new(0.0, 0.0, 0.0); width]; height];
+    let mut weights = vec![vec![0.0; width]; height];
+    let mut aovs = vec![vec![vec![Vec3::new(0.0, 0.0, 0.0); width]; height]; aovCount];
+    for sum in sums.iter_mut().flatten() {
+        *sum = readVec3(reader)?;
+    }
+    for weight in weights.iter_mut().flatten() {
+        *weight = readF64(reader)?;
+    }
+    for sum in aovs.iter_mut().flatten().flatten() {
+        *sum = readVec3(reader)?;
+    }
+    return Ok((sums, weights, aovs));
+}
+
+impl TileResult {
+    // checkpoint文件和分布式渲染的时候worker发回来的都是这个格式
+    pub(crate) fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        let splats = &self.splats;
+        writeU64(writer, self.index as u64)?;
+        writeStatistics(writer, &self.pixels)?;
+        writeU64(writer, splats.x as u64)?;
+        writeU64(writer, splats.y as u64)?;
+        writeU64(
+            writer,
+            splats.weights.first().map(|v| v.len()).unwrap_or(0) as u64,
+        )?;
+        writeU64(writer, splats.weights.len() as u64)?;
+        return writeSums(writer, &splats.sums, &splats.weights, &splats.aovs);
+    }
+
+    // 读的时候检查一下tile和splat的范围，坏掉的数据不至于让后面合并的时候越界
+    // statistics是渲染这个tile之前的，每个pixel的样本数只会变多，不然合并的时候算新样本数会溢出
+    pub(crate) fn read(
+        reader: &mut dyn Read,
+        renderer: &Renderer,
+        tiles: &[Tile],
+        statistics: &[Vec<PixelStatistics>],
+    ) -> Result<Self> {
+        let invalid = || Error::new(ErrorKind::InvalidData, "tile out of range");
+
+        let index = readU64(reader)? as usize;
+        let tile = tiles.get(index).ok_or_else(invalid)?;
+        let pixels = readStatistics(reader, tile.width(), tile.height())?;
+        for (j, row) in pixels.iter().enumerate() {
+            for (i, pixel) in row.iter().enumerate() {
+                if pixel.sampleCount() < statistics[tile.y() + j][tile.x() + i].sampleCount() {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "tile has fewer samples than before",
+                    ));
+                }
+            }
+        }
+
+        let x = readU64(reader)? as usize;
+        let y = readU64(reader)? as usize;
+        let width = readU64(reader)? as usize;
+        let height = readU64(reader)? as usize;
+        let outside = |start: usize, length: usize, limit: usize| -> bool {
+            return start.checked_add(length).is_none_or(|end| end > limit);
+        };
+        if outside(x, width, renderer.width()) || outside(y, height, renderer.height()) {
+            return Err(invalid());
+        }
+        let (sums, weights, aovs) = readSums(reader, width, height, renderer.aovs().len())?;
+
+        return Ok(Self {
+            index: index,
+            pixels: pixels,
+            splats: Splats {
+                x: x,
+                y: y,
+                sums: sums,
+                weights: weights,
+                aovs: aovs,
+            },
+        });
+    }
+}
+
+impl Renderer {
+    // 和render()一样，但是每隔interval把已经渲染完的tile存到path，进程挂了下次还能接着渲染
+    // path已经存在的话就从里面接着渲染，结果和一口气渲染完一模一样。渲染完会把path删掉
+    // checkpoint必须是同样设置的Renderer存的，不然返回InvalidData
+    // 取消了的话，把已经渲染完的tile存下来，返回Interrupted，下次还能接着渲染
+    // 只能一口气渲染完，设了timeBudget或者targetNoise的话返回InvalidInput
+    pub fn renderWithCheckpoint(
+        &self,
+        world: &dyn Hit,
+        path: &Path,
+        interval: Duration,
+    ) -> Result<Film> {
+        self.checkSinglePass()?;
+        let mut accumulation = Accumulation::new(self.width(), self.height(), self.aovs().len());
+        let mut done = vec![];
+
+        if path.exists() {
+            let (resumed, results) = self.readCheckpoint(&mut BufReader::new(File::open(path)?))?;
+            accumulation = resumed;
+            done = results;
+        } else {
+            // 先存一次，路径写不进去的话马上就知道，不用等到渲染了一半
+            self.writeCheckpoint(path, &accumulation, &[])?;
+        }
+
+        let mut last = Instant::now();
+        let mut error = Ok(());
+        let mut progress = Progress::new(self.tiles().len(), None);
+        self.renderPass(
+            world,
+            self.subPixelSampleCount(),
+            &mut accumulation,
+            done,
+            &mut progress,
+            &mut |accumulation, results| {
+                let cancelled = self.cancellationToken().isCancelled();
+                if error.is_ok() && (last.elapsed() >= interval || cancelled) {
+                    error = self.writeCheckpoint(path, accumulation, results);
+                    last = Instant::now();
+                }
+            },
+        );
+        error?;
+
+        if self.cancellationToken().isCancelled() {
+            return Err(Error::new(ErrorKind::Interrupted, "render cancelled"));
+        }
+
+        std::fs::remove_file(path)?;
+        return Ok(accumulation.film(self.aovs()));
+    }
+
+    // 影响渲染结果的设置。checkpoint文件开头和worker连上来的时候都要对一下，对不上就不能用
+    // 长度是固定的，不然worker和coordinator会在握手的时候互相等着对方
+    pub(crate) fn fingerprint(&self) -> Vec<u64> {
+        let aovs: Vec<&str> = self.aovs().iter().map(|aov| aov.name()).collect();
+        return vec![
+            self.width() as u64,
+            self.height() as u64,
+            self.region().x() as u64,
+            self.region().y() as u64,
+            self.region().width() as u64,
+            self.region().height() as u64,
+            self.tileSize() as u64,
+            self.seed(),
+            self.subPixelSampleCount() as u64,
+            self.minSampleCount() as u64,
+            self.errorThreshold().to_bits(),
+            self.maxDepth() as u64,
+            hashString(self.integrator().name()),
+            hashString(self.sampler().name()),
+            hashString(&format!("{:?}", self.filter())), // 种类和参数都在里面
+            hashString(&aovs.join(",")),
+            self.sceneHash(),
+        ];
+    }
+
+    // 先写到一个临时文件里再改名，写到一半挂了也不会把上一个checkpoint弄坏
+    fn writeCheckpoint(
+        &self,
+        path: &Path,
+        accumulation: &Accumulation,
+        results: &[TileResult],
+    ) -> Result<()> {
+        let temporary = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        for value in self.fingerprint() {
+            writeU64(&mut writer, value)?;
+        }
+
+        writeStatistics(&mut writer, &accumulation.statistics)?;
+        writeSums(
+            &mut writer,
+            &accumulation.sums,
+            &accumulation.weights,
+            &accumulation.aovs,
+        )?;
+
+        writeU64(&mut writer, results.len() as u64)?;
+        for result in results.iter() {
+            result.write(&mut writer)?;
+        }
+
+        writer.flush()?;
+        drop(writer);
+        return std::fs::rename(&temporary, path);
+    }
+
+    fn readCheckpoint(&self, reader: &mut dyn Read) -> Result<(Accumulation, Vec<TileResult>)> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(invalid("not a checkpoint file"));
+        }
+        for value in self.fingerprint() {
+            if readU64(reader)? != value {
+                return Err(invalid(
+                    "checkpoint was saved with different render settings",
+                ));
+            }
+        }
+
+        let (width, height, aovCount) = (self.width(), self.height(), self.aovs().len());
+        let statistics = readStatistics(reader, width, height)?;
+        let (sums, weights, aovs) = readSums(reader, width, height, aovCount)?;
+        let accumulation = Accumulation {
+            statistics: statistics,
+            sums: sums,
+            weights: weights,
+            aovs: aovs,
+        };
+
+        // 同一个tile出现两次的话会被加两遍
+        let tiles = self.tiles();
+        let count = readU64(reader)? as usize;
+        let mut results: Vec<TileResult> = vec![];
+        for _ in 0..count {
+            let result = TileResult::read(reader, self, &tiles, &accumulation.statistics)?;
+            if results.iter().any(|v| v.index == result.index) {
+                return Err(invalid("tile saved twice"));
+            }
+            results.push(result);
+        }
+
+        return Ok((accumulation, results));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aov::Aov;
+    use crate::filter::MitchellFilter;
+    use crate::integrator::Recursive;
+    use crate::ray::Hit;
+    use crate::ray::HitRecord;
+    use crate::ray::Ray;
+    use crate::render::tests::testRenderer;
+    use crate::render::tests::testScene;
+    use crate::render::PixelStatistics;
+    use crate::render::RendererBuilder;
+    use crate::render::Splats;
+    use crate::render::TileResult;
+    use crate::sampler::Sampler;
+    use crate::sampler::SobolSampler;
+    use crate::vec3::Vec3;
+
+    use std::io::ErrorKind;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // 渲染到一半进程挂了（这里用panic模拟），从checkpoint接着渲染，结果和一口气渲染完一样
+    #[test]
+    fn checkpointResumesIdentically() {
+        struct Crashing<'a> {
+            world: &'a dyn Hit,
+            remaining: AtomicUsize,
+        }
+
+        impl Hit for Crashing<'_> {
+            fn hit(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<HitRecord> {
+                if self.remaining.fetch_sub(1, Ordering::SeqCst) == 0 {
+                    panic!("crash");
+                }
+                return self.world.hit(ray, sampler);
+            }
+        }
+
+        let world = testScene();
+        let build = || -> RendererBuilder {
+            return testRenderer(12, 12)
+                .filter(Arc::new(MitchellFilter::default()))
+                .aovs(vec![Aov::Depth, Aov::Normal])
+                .threadCount(1);
+        };
+        let renderer = build().build();
+        let expected = renderer.render(&world);
+
+        let path =
+            std::env::temp_dir().join(format!("ray-tracer-checkpoint-{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // 一个tile是16个pixel x 4个样本，每个样本最多弹射几次，大概渲染了一半的时候挂掉
+        let crashing = Crashing {
+            world: &world,
+            remaining: AtomicUsize::new(600),
+        };
+        let crashed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            renderer.renderWithCheckpoint(&crashing, &path, Duration::ZERO)
+        }));
+        assert!(crashed.is_err());
+        assert!(path.exists());
+
+        // 设置不一样的Renderer不能用这个checkpoint
+        for other in [
+            build().seed(1),
+            build().maxDepth(5),
+            build().aovs(vec![Aov::Depth, Aov::Albedo]),
+            build().filter(Arc::new(MitchellFilter::new(2.0, 0.5, 0.5))),
+            build().integrator(Arc::new(Recursive)),
+            build().sampler(Arc::new(SobolSampler::new(0))),
+            build().sceneHash(1),
+        ] {
+            let error = other
+                .build()
+                .renderWithCheckpoint(&world, &path, Duration::ZERO)
+                .unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+
+        let film = renderer
+            .renderWithCheckpoint(&world, &path, Duration::ZERO)
+            .unwrap();
+        assert_eq!(film, expected);
+        assert!(!path.exists());
+    }
+
+    // worker发回来的splat范围或者样本数是坏的，加起来溢出了也只是返回错误，不会panic
+    #[test]
+    fn corruptTileIsRejected() {
+        let renderer = testRenderer(8, 8).build();
+        let tiles = renderer.tiles();
+        let empty = vec![vec![PixelStatistics::new(); 8]; 8];
+        let read =
+            |x: usize, y: usize, pixel: PixelStatistics, statistics: &[Vec<PixelStatistics>]| {
+                let result = TileResult {
+                    index: 0,
+                    pixels: vec![vec![pixel; 4]; 4],
+                    splats: Splats {
+                        x: x,
+                        y: y,
+                        sums: vec![vec![Vec3::new(0.0, 0.0, 0.0); 2]; 2],
+                        weights: vec![vec![0.0; 2]; 2],
+                        aovs: vec![],
+                    },
+                };
+                let mut bytes = vec![];
+                result.write(&mut bytes).unwrap();
+                return TileResult::read(&mut &bytes[..], &renderer, &tiles, statistics);
+            };
+
+        for (x, y) in [(usize::MAX, 0), (0, usize::MAX), (7, 0)] {
+            let error = read(x, y, PixelStatistics::new(), &empty).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+
+        // 样本数比渲染之前还少；没被挡住的样本比所有样本还多
+        let mut before = empty.clone();
+        before[1][2].add(Vec3::new(1.0, 1.0, 1.0));
+        let mut inconsistent = PixelStatistics::new();
+        inconsistent.unblockedCount = 1;
+        for (pixel, statistics) in [(PixelStatistics::new(), &before), (inconsistent, &empty)] {
+            let error = read(0, 0, pixel, statistics).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+        }
+        assert!(read(0, 0, PixelStatistics::new(), &empty).is_ok());
+    }
+}
@@ -0,0 +1,380 @@
+use crate::aov::Aov;
+use crate::film::Film;
+use crate::vec3::Vec3;
+
+// 渲染完以后在film上做的降噪，用的是AOV引导的non-local means
+// 一个pixel的结果是周围窗口里所有pixel颜色的加权平均，权重看两件事：
+// 1. 以两个pixel为中心的小块（patch）颜色像不像，噪点是随机的，真正的细节在整个patch上都会有差别
+// 2. albedo、法向量、深度这些AOV像不像，它们几乎没有噪点，物体的边缘、贴图的边缘在上面都很清楚，可以挡住模糊
+// film里没有的AOV就不用。有albedo的话先把颜色除以albedo再降噪，最后再乘回去，这样贴图的细节不会被抹掉
+// <https://www.cs.umd.edu/~zwicker/publications/AdaptiveRenderingNLM-SIGA12.pdf>
+#[derive(Clone, Debug)]
+pub struct Denoiser {
+    radius: usize,      // 窗口半径，窗口是(2 * radius + 1)^2个pixel
+    patchRadius: usize, // 比较颜色用的patch的半径
+    colorSigma: f64,    // 越大越模糊
+    albedoSigma: f64,
+    normalSigma: f64,
+    depthSigma: f64, // 深度差除以深度，这样远近的物体用同一个标准
+    threadCount: usize,
+}
+
+pub struct DenoiserBuilder {
+    denoiser: Denoiser,
+}
+
+impl DenoiserBuilder {
+    pub fn build(self) -> Denoiser {
+        return self.denoiser;
+    }
+
+    pub fn radius(mut self, radius: usize) -> Self {
+        self.denoiser.radius = radius;
+        return self;
+    }
+
+    pub fn patchRadius(mut self, patchRadius: usize) -> Self {
+        self.denoiser.patchRadius = patchRadius;
+        return self;
+    }
+
+    // sigma都是除数，0的话自己和自己比也是0 / 0，整张图都变成NaN
+    pub fn colorSigma(mut self, colorSigma: f64) -> Self {
+        self.denoiser.colorSigma = colorSigma.max(Denoiser::MIN_SIGMA);
+        return self;
+    }
+
+    pub fn albedoSigma(mut self, albedoSigma: f64) -> Self {
+        self.denoiser.albedoSigma = albedoSigma.max(Denoiser::MIN_SIGMA);
+        return self;
+    }
+
+    pub fn normalSigma(mut self, normalSigma: f64) -> Self {
+        self.denoiser.normalSigma = normalSigma.max(Denoiser::MIN_SIGMA);
+        return self;
+    }
+
+    pub fn depthSigma(mut self, depthSigma: f64) -> Self {
+        self.denoiser.depthSigma = depthSigma.max(Denoiser::MIN_SIGMA);
+        return self;
+    }
+
+    pub fn threadCount(mut self, threadCount: usize) -> Self {
+        self.denoiser.threadCount = threadCount.max(1);
+        return self;
+    }
+}
+
+// 一个pixel周围用来比较的东西
+struct Features {
+    width: usize,
+    height: usize,
+    colors: Vec<Vec3>, // 除过albedo的
+    albedos: Option<Vec<Vec3>>,
+    normals: Option<Vec<Vec3>>,
+    depths: Option<Vec<f64>>,
+}
+
+impl Denoiser {
+    // 除以albedo的时候加上这个，全黑的albedo（比如灯和背景）也不会除以0
+    const ALBEDO_EPSILON: f64 = 0.01;
+    // builder里sigma最小是这个
+    const MIN_SIGMA: f64 = 1e-6;
+
+    pub fn builder() -> DenoiserBuilder {
+        DenoiserBuilder {
+            denoiser: Denoiser {
+                radius: 5,
+                patchRadius: 1,
+                colorSigma: 0.5,
+                albedoSigma: 0.1,
+                normalSigma: 0.3,
+                depthSigma: 0.1,
+                threadCount: std::thread::available_parallelism()
+                    .map(|v| v.get())
+                    .unwrap_or(1),
+            },
+        }
+    }
+
+    pub fn radius(&self) -> usize {
+        return self.radius;
+    }
+
+    pub fn patchRadius(&self) -> usize {
+        return self.patchRadius;
+    }
+
+    pub fn colorSigma(&self) -> f64 {
+        return self.colorSigma;
+    }
+
+    pub fn albedoSigma(&self) -> f64 {
+        return self.albedoSigma;
+    }
+
+    pub fn normalSigma(&self) -> f64 {
+        return self.normalSigma;
+    }
+
+    pub fn depthSigma(&self) -> f64 {
+        return self.depthSigma;
+    }
+
+    pub fn threadCount(&self) -> usize {
+        return self.threadCount;
+    }
+
+    // 返回降噪之后的film，AOV原样保留
+    pub fn denoise(&self, film: &Film) -> Film {
+        let features = self.features(film);
+        let mut res = film.clone();
+
+        // 按行分给各个线程，每个pixel只读features，互不影响
+        let rowsPerThread = (film.height() + self.threadCount - 1) / self.threadCount.max(1);
+        let mut rows = vec![];
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..film.height())
+                .step_by(rowsPerThread.max(1))
+                .map(|start| {
+                    let features = &features;
+                    let end = (start + rowsPerThread).min(film.height());
+                    scope.spawn(move || {
+                        let mut res = vec![];
+                        for y in start..end {
+                            for x in 0..features.width {
+                                res.push(self.denoisePixel(features, x, y));
+                            }
+                        }
+                        return res;
+                    })
+                })
+                .collect();
+
+            for handle in handles {
+                rows.extend(handle.join().unwrap());
+            }
+        });
+
+        for (i, color) in rows.iter().enumerate() {
+            res.setPixel(i % film.width(), i / film.width(), color);
+        }
+
+        return res;
+    }
+
+    fn features(&self, film: &Film) -> Features {
+        let layer = |aov: Aov| -> Option<Vec<Vec3>> {
+            let layer = film.aov(aov.name())?;
+            let mut res = vec![];
+            for y in 0..film.height() {
+                for x in 0..film.width() {
+                    res.push(layer.pixel(x, y));
+                }
+            }
+            return Some(res);
+        };
+
+        let albedos = layer(Aov::Albedo);
+        let mut colors = vec![];
+        for y in 0..film.height() {
+            for x in 0..film.width() {
+                let color = film.pixel(x, y);
+                if let Some(albedos) = &albedos {
+                    colors.push(color / Self::demodulation(&albedos[y * film.width() + x]));
+                } else {
+                    colors.push(color);
+                }
+            }
+        }
+
+        return Features {
+            width: film.width(),
+            height: film.height(),
+            colors: colors,
+            albedos: albedos,
+            normals: layer(Aov::Normal),
+            depths: layer(Aov::Depth).map(|v| v.iter().map(|v| v.x()).collect()),
+        };
+    }
+
+    fn demodulation(albedo: &Vec3) -> Vec3 {
+        return *albedo + Vec3::new(1.0, 1.0, 1.0) * Self::ALBEDO_EPSILON;
+    }
+
+    fn denoisePixel(&self, features: &Features, x: usize, y: usize) -> Vec3 {
+        let p = y * features.width + x;
+        let low = |v: usize| v.saturating_sub(self.radius);
+        let xs = low(x)..(x + self.radius + 1).min(features.width);
+        let ys = low(y)..(y + self.radius + 1).min(features.height);
+
+        let mut sum = Vec3::new(0.0, 0.0, 0.0);
+        let mut weightSum = 0.0;
+
+        for qy in ys {
+            for qx in xs.clone() {
+                let q = qy * features.width + qx;
+                let mut distance =
+                    self.patchDistance(features, (x, y), (qx, qy)) / self.colorSigma.powi(2);
+
+                if let Some(albedos) = &features.albedos {
+                    let difference = albedos[p] - albedos[q];
+                    distance += difference.dot(&difference) / self.albedoSigma.powi(2);
+                }
+                if let Some(normals) = &features.normals {
+                    let difference = normals[p] - normals[q];
+                    distance += difference.dot(&difference) / self.normalSigma.powi(2);
+                }
+                if let Some(depths) = &features.depths {
+                    // 一个打到了东西一个没打到（深度是0）的话，差别是无穷大
+                    let scale = depths[p].max(depths[q]).max(1e-6);
+                    distance += ((depths[p] - depths[q]) / scale / self.depthSigma).powi(2);
+                }
+
+                let weight = (-distance).exp();
+                sum += features.colors[q] * weight;
+                weightSum += weight;
+            }
+        }
+
+        // 自己和自己的距离是0，weightSum至少是1
+        let color = sum / weightSum;
+        if let Some(albedos) = &features.albedos {
+            return color * Self::demodulation(&albedos[p]);
+        } else {
+            return color;
+        }
+    }
+
+    // 两个patch每个通道的平均相对差的平方，除以两边的亮度，暗的地方和亮的地方用同一个标准
+    // patch超出画面的部分不算
+    fn patchDistance(&self, features: &Features, p: (usize, usize), q: (usize, usize)) -> f64 {
+        let r = self.patchRadius as isize;
+        let mut sum = 0.0;
+        let mut count = 0;
+
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let (px, py) = (p.0 as isize + dx, p.1 as isize + dy);
+                let (qx, qy) = (q.0 as isize + dx, q.1 as isize + dy);
+                let inside = |x: isize, y: isize| -> bool {
+                    x >= 0 && y >= 0 && x < features.width as isize && y < features.height as isize
+                };
+                if !inside(px, py) || !inside(qx, qy) {
+                    continue;
+                }
+
+                let a = features.colors[py as usize * features.width + px as usize];
+                let b = features.colors[qy as usize * features.width + qx as usize];
+                for (a, b) in [(a.r(), b.r()), (a.g(), b.g()), (a.b(), b.b())].iter() {
+                    sum += (a - b).powi(2) / (1e-2 + a * a + b * b);
+                }
+                count += 3;
+            }
+        }
+
+        if count == 0 {
+            return 0.0;
+        }
+        return sum / count as f64;
+    }
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aov::Aov;
+    use crate::denoise::Denoiser;
+    use crate::film::Film;
+    use crate::sampler::Pcg32;
+    use crate::vec3::Vec3;
+
+    // 左半边albedo是0.2，右半边是0.8，颜色上加了很多噪点
+    // 降噪之后离真实值近了很多，而且边缘两边没有混到一起
+    #[test]
+    fn denoiseKeepsAlbedoEdges() {
+        let (width, height) = (32, 16);
+        let albedo = |x: usize| -> f64 {
+            if x < width / 2 {
+                return 0.2;
+            } else {
+                return 0.8;
+            }
+        };
+
+        let mut random = Pcg32::new(1, 0);
+        let mut film = Film::new(width, height);
+        let mut albedos = Film::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let a = albedo(x);
+                let noise = |random: &mut Pcg32| a * 0.5 * (random.nextF64() - 0.5);
+                let color = Vec3::new(
+                    a + noise(&mut random),
+                    a + noise(&mut random),
+                    a + noise(&mut random),
+                );
+                film.setPixel(x, y, &color);
+                albedos.setPixel(x, y, &Vec3::new(a, a, a));
+            }
+        }
+        film.setAov(Aov::Albedo.name(), albedos);
+
+        let error = |film: &Film| -> f64 {
+            let mut sum = 0.0;
+            for y in 0..height {
+                for x in 0..width {
+                    let a = albedo(x);
+                    let difference = film.pixel(x, y) - Vec3::new(a, a, a);
+                    sum += difference.dot(&difference);
+                }
+            }
+            return (sum / (width * height) as f64).sqrt();
+        };
+
+        let denoised = Denoiser::builder().threadCount(3).build().denoise(&film);
+        assert!(error(&denoised) < 0.25 * error(&film));
+        assert!((denoised.pixel(width / 2 - 1, 8).g() - 0.2).abs() < 0.02);
+        assert!((denoised.pixel(width / 2, 8).g() - 0.8).abs() < 0.05);
+        assert_eq!(denoised.aov("albedo"), film.aov("albedo"));
+    }
+
+    // sigma给0的时候不能出NaN，相当于不降噪
+    #[test]
+    fn zeroSigmasKeepFilm() {
+        let (width, height) = (8, 8);
+        let mut random = Pcg32::new(2, 0);
+        let mut film = Film::new(width, height);
+        let mut albedos = Film::new(width, height);
+        let mut depths = Film::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let value = random.nextF64();
+                film.setPixel(x, y, &Vec3::new(value, value, value));
+                albedos.setPixel(x, y, &Vec3::new(0.5, 0.5, 0.5));
+                depths.setPixel(x, y, &Vec3::new(1.0, 1.0, 1.0));
+            }
+        }
+        film.setAov(Aov::Albedo.name(), albedos);
+        film.setAov(Aov::Depth.name(), depths);
+
+        let denoised = Denoiser::builder()
+            .colorSigma(0.0)
+            .albedoSigma(0.0)
+            .normalSigma(0.0)
+            .depthSigma(0.0)
+            .build()
+            .denoise(&film);
+        for y in 0..height {
+            for x in 0..width {
+                let difference = denoised.pixel(x, y) - film.pixel(x, y);
+                assert!(difference.length() < 1e-4);
+            }
+        }
+    }
+}
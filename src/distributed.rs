@@ -0,0 +1,398 @@
+use crate::checkpoint::readU64;
+use crate::checkpoint::writeU64;
+use crate::film::Film;
+use crate::ray::Hit;
+use crate::render::Accumulation;
+use crate::render::PixelStatistics;
+use crate::render::Progress;
+use crate::render::Renderer;
+use crate::render::TileResult;
+
+use std::collections::VecDeque;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Result;
+use std::io::Write;
+use std::net::Shutdown;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::Sender;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// 分布式渲染的协议：worker连上来先发magic和fingerprint()，coordinator回一个1表示设置对得上
+// 然后coordinator发tile的编号，worker渲染完发回TileResult，直到coordinator发NO_MORE_TILES
+const WORKER_MAGIC: &[u8; 8] = b"RTWORK03";
+const NO_MORE_TILES: u64 = u64::MAX;
+
+impl Renderer {
+    // 分布式渲染的coordinator，自己不渲染，只在listener上等worker连进来，把tile一个一个发出去，收回来合到一起
+    // worker必须是同一个场景、同样设置的Renderer，连上来的时候会对一下设置，对不上的直接断开
+    // 一个worker断了或者超过workerTimeout没动静的话，它手上的tile还给别的worker渲染。结果和在一个进程里render()一模一样
+    // 取消了的话返回Interrupted，worker那边会收到没有tile了
+    // 和renderWithCheckpoint()一样只能一口气渲染完，设了timeBudget或者targetNoise的话返回InvalidInput
+    pub fn renderDistributed(&self, listener: TcpListener) -> Result<Film> {
+        self.checkSinglePass()?;
+        let tiles = self.tiles();
+        let pending = Mutex::new((0..tiles.len()).collect::<VecDeque<usize>>());
+        let changed = Condvar::new(); // pending变了或者渲染完了
+        let finished = AtomicBool::new(false);
+        let connections = Mutex::new(vec![]); // 每个worker连接的一个副本，渲染完了用来把卡住的连接断开
+        let mut results = vec![];
+        let mut accumulation = Accumulation::new(self.width(), self.height(), self.aovs().len());
+        let mut progress = Progress::new(tiles.len(), None);
+
+        // 区域是空的话一个tile都没有，也不用等worker了
+        if tiles.is_empty() {
+            return Ok(accumulation.film(self.aovs()));
+        }
+
+        // 不然accept()会一直卡住，渲染完了也退不出来
+        listener.set_nonblocking(true)?;
+
+        std::thread::scope(|scope| {
+            let (sender, receiver) = channel();
+            let pending = &pending;
+            let changed = &changed;
+            let finished = &finished;
+            let connections = &connections;
+            let tiles = &tiles;
+            let statistics = &accumulation.statistics;
+
+            scope.spawn(move || {
+                while !finished.load(Ordering::SeqCst) {
+                    match listener.accept() {
+                        Ok((stream, _)) => {
+                            // 拿着锁看finished，不然可能正好错过下面断开所有连接的那一下
+                            let mut connections = connections.lock().unwrap();
+                            if finished.load(Ordering::SeqCst) {
+                                break;
+                            }
+                            if let Ok(connection) = stream.try_clone() {
+                                connections.push(connection);
+                            }
+                            drop(connections);
+                            let sender = sender.clone();
+                            scope.spawn(move || {
+                                self.serveWorker(
+                                    stream, statistics, pending, changed, finished, sender,
+                                )
+                            });
+                        }
+                        Err(_) => {
+                            std::thread::sleep(Duration::from_millis(10));
+                        }
+                    }
+                }
+            });
+
+            // 隔一会儿看一下是不是取消了
+            while results.len() < tiles.len() && !self.cancellationToken().isCancelled() {
+                if let Ok(result) = receiver.recv_timeout(Duration::from_millis(100)) {
+                    progress.add(result.sampleCount(tiles, statistics));
+                    if let Some(callback) = self.progress() {
+                        callback(&progress);
+                    }
+                    results.push(result);
+                }
+            }
+
+            // 拿着锁改，不然等着的线程可能刚看完finished还没开始wait，就错过了notify
+            let queue = pending.lock().unwrap();
+            finished.store(true, Ordering::SeqCst);
+            drop(queue);
+            changed.notify_all();
+
+            // 还在等握手或者等tile的连接（什么都不发的客户端、取消的时候正在渲染的worker）不用再等了
+            // 只关读的一半，在等下一个tile的worker还能收到没有tile了
+            for connection in connections.lock().unwrap().iter() {
+                let _ = connection.shutdown(Shutdown::Read);
+            }
+        });
+
+        if self.cancellationToken().isCancelled() {
+            return Err(Error::new(ErrorKind::Interrupted, "render cancelled"));
+        }
+        self.merge(&mut accumulation, results);
+        return Ok(accumulation.film(self.aovs()));
+    }
+
+    // 和一个worker的连接。出错了就把没做完的tile放回去，这个连接就不管了
+    fn serveWorker(
+        &self,
+        stream: TcpStream,
+        statistics: &[Vec<PixelStatistics>],
+        pending: &Mutex<VecDeque<usize>>,
+        changed: &Condvar,
+        finished: &AtomicBool,
+        sender: Sender<TileResult>,
+    ) {
+        let handshake = || -> Result<(BufReader<&TcpStream>, BufWriter<&TcpStream>)> {
+            stream.set_nonblocking(false)?;
+            stream.set_read_timeout(Some(self.workerTimeout()))?;
+            stream.set_write_timeout(Some(self.workerTimeout()))?;
+            let mut reader = BufReader::new(&stream);
+            let mut writer = BufWriter::new(&stream);
+
+            let mut magic = [0; 8];
+            reader.read_exact(&mut magic)?;
+            let mut matched = &magic == WORKER_MAGIC;
+            for value in self.fingerprint() {
+                matched &= readU64(&mut reader)? == value;
+            }
+            writeU64(&mut writer, matched as u64)?;
+            writer.flush()?;
+
+            if !matched {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "worker has different render settings",
+                ));
+            }
+            return Ok((reader, writer));
+        };
+        let (mut reader, mut writer) = match handshake() {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        let tiles = self.tiles();
+
+        loop {
+            // 没有tile可发的时候，可能别的worker还会断掉把tile放回来，所以要等到真的渲染完
+            let mut queue = pending.lock().unwrap();
+            while queue.is_empty() && !finished.load(Ordering::SeqCst) {
+                queue = changed.wait(queue).unwrap();
+            }
+            // 取消了的话queue里可能还有tile，也不发了
+            let index = if finished.load(Ordering::SeqCst) {
+                None
+            } else {
+                queue.pop_front()
+            };
+            drop(queue);
+
+            let index = match index {
+                Some(index) => index,
+                None => {
+                    let _ = writeU64(&mut writer, NO_MORE_TILES).and_then(|_| writer.flush());
+                    return;
+                }
+            };
+
+            let mut job = || -> Result<TileResult> {
+                writeU64(&mut writer, index as u64)?;
+                writer.flush()?;
+                let result = TileResult::read(&mut reader, self, &tiles, statistics)?;
+                if result.index != index {
+                    return Err(Error::new(ErrorKind::InvalidData, "wrong tile"));
+                }
+                return Ok(result);
+            };
+
+            match job() {
+                Ok(result) => {
+                    if sender.send(result).is_err() {
+                        return;
+                    }
+                }
+                Err(_) => {
+                    pending.lock().unwrap().push_back(index);
+                    changed.notify_all();
+                    return;
+                }
+            }
+        }
+    }
+
+    // 分布式渲染的worker：开threadCount个连接连到coordinator，每个连接一个线程，渲染它发过来的tile，直到它说没有了
+    // 取消了的话返回Interrupted；设了timeBudget或者targetNoise的话返回InvalidInput
+    pub fn renderWorker<A>(&self, world: &dyn Hit, address: A) -> Result<()>
+    where
+        A: ToSocketAddrs,
+    {
+        self.checkSinglePass()?;
+        let addresses: Vec<_> = address.to_socket_addrs()?.collect();
+        let tiles = self.tiles();
+        // 只渲染一遍，所以每个pixel都是从0个样本开始
+        let statistics = vec![vec![PixelStatistics::new(); self.width()]; self.height()];
+
+        return std::thread::scope(|scope| -> Result<()> {
+            let handles: Vec<_> = (0..self.threadCount())
+                .map(|_| {
+                    let addresses = &addresses;
+                    let tiles = &tiles;
+                    let statistics = &statistics;
+
+                    scope.spawn(move || -> Result<()> {
+                        let stream = TcpStream::connect(&addresses[..])?;
+                        let mut reader = BufReader::new(&stream);
+                        let mut writer = BufWriter::new(&stream);
+
+                        writer.write_all(WORKER_MAGIC)?;
+                        for value in self.fingerprint() {
+                            writeU64(&mut writer, value)?;
+                        }
+                        writer.flush()?;
+                        if readU64(&mut reader)? != 1 {
+                            return Err(Error::new(
+                                ErrorKind::InvalidData,
+                                "coordinator has different render settings",
+                            ));
+                        }
+
+                        loop {
+                            let index = readU64(&mut reader)?;
+                            if index == NO_MORE_TILES {
+                                return Ok(());
+                            }
+                            // 断开以后coordinator会把这个tile交给别的worker
+                            if self.cancellationToken().isCancelled() {
+                                return Err(Error::new(ErrorKind::Interrupted, "render cancelled"));
+                            }
+                            let tile = tiles.get(index as usize).ok_or_else(|| {
+                                Error::new(ErrorKind::InvalidData, "tile out of range")
+                            })?;
+
+                            let (pixels, splats) = self.renderTile(
+                                world,
+                                tile,
+                                self.subPixelSampleCount(),
+                                statistics,
+                            );
+                            let result = TileResult {
+                                index: index as usize,
+                                pixels: pixels,
+                                splats: splats,
+                            };
+                            result.write(&mut writer)?;
+                            writer.flush()?;
+                        }
+                    })
+                })
+                .collect();
+
+            for handle in handles {
+                handle.join().unwrap()?;
+            }
+            return Ok(());
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aov::Aov;
+    use crate::checkpoint::readU64;
+    use crate::checkpoint::writeU64;
+    use crate::distributed::WORKER_MAGIC;
+    use crate::filter::MitchellFilter;
+    use crate::render::tests::testRenderer;
+    use crate::render::tests::testScene;
+    use crate::render::RendererBuilder;
+    use crate::vec3::Vec3;
+
+    use std::io::ErrorKind;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::net::TcpStream;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // 一个coordinator带两个worker，结果和一个进程里渲染的一样；设置不一样的worker连不上
+    #[test]
+    fn distributedMatchesLocalRender() {
+        let world = testScene();
+        let build = || -> RendererBuilder {
+            return testRenderer(12, 10)
+                .filter(Arc::new(MitchellFilter::default()))
+                .aovs(vec![Aov::Depth]);
+        };
+
+        let expected = build().threadCount(1).build().render(&world);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let film = std::thread::scope(|scope| {
+            let coordinator = scope.spawn(|| build().build().renderDistributed(listener));
+
+            let error = build()
+                .seed(7)
+                .build()
+                .renderWorker(&world, address)
+                .unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+            let workers: Vec<_> = [1, 2]
+                .iter()
+                .map(|threadCount| {
+                    let renderer = build().threadCount(*threadCount).build();
+                    let world = &world;
+                    scope.spawn(move || renderer.renderWorker(world, address))
+                })
+                .collect();
+            for worker in workers {
+                worker.join().unwrap().unwrap();
+            }
+
+            return coordinator.join().unwrap().unwrap();
+        });
+
+        assert_eq!(film, expected);
+
+        // 区域是空的话没有tile，不等worker直接返回
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let renderer = build().region(0, 0, 0, 0).build();
+        std::thread::spawn(move || {
+            let _ = sender.send(renderer.renderDistributed(listener).unwrap());
+        });
+        let film = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(film.pixel(5, 5), Vec3::new(0.0, 0.0, 0.0));
+    }
+
+    // 连上来什么都不发的客户端不会让coordinator渲染完了还一直等着；领了tile就不动了的，超时以后tile交给别的worker
+    #[test]
+    fn silentWorkerDoesNotHangCoordinator() {
+        let world = testScene();
+        let build = || -> RendererBuilder {
+            return testRenderer(8, 8).threadCount(1);
+        };
+        let expected = build().build().render(&world);
+
+        for stall in [false, true] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let renderer = build().workerTimeout(Duration::from_millis(200)).build();
+            let renderer = if stall { renderer } else { build().build() };
+            std::thread::spawn(move || {
+                let _ = sender.send(renderer.renderDistributed(listener).unwrap());
+            });
+
+            let silent = TcpStream::connect(address).unwrap();
+            if stall {
+                let mut writer = &silent;
+                writer.write_all(WORKER_MAGIC).unwrap();
+                for value in build().build().fingerprint() {
+                    writeU64(&mut writer, value).unwrap();
+                }
+                let mut reader = &silent;
+                assert_eq!(readU64(&mut reader).unwrap(), 1);
+                readU64(&mut reader).unwrap(); // 领了一个tile，不渲染
+            }
+            build().build().renderWorker(&world, address).unwrap();
+
+            let film = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+            assert_eq!(film, expected);
+            drop(silent);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::io::{Error, ErrorKind, Result};
+
+// 分段常数的概率分布，给一串非负的函数值，按值的大小比例随机取点
+// 参考 <http://www.pbr-book.org/3ed-2018/Monte_Carlo_Integration/Sampling_Random_Variables.html#PiecewiseConstant1DFunctions>
+#[derive(Clone, Debug)]
+pub struct Distribution1D {
+    function: Vec<f64>,
+    cdf: Vec<f64>, // 长度比function多1，cdf[0] = 0，cdf[n] = 1
+    integral: f64, // function在[0, 1]上的积分
+}
+
+impl Distribution1D {
+    // 至少要有一段，不然pdf()和sample()都不知道返回什么
+    pub fn new(function: Vec<f64>) -> Result<Self> {
+        if function.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "distribution needs at least one value",
+            ));
+        }
+        let n = function.len();
+        let mut cdf = vec![0.0; n + 1];
+
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + function[i].max(0.0) / n as f64;
+        }
+
+        let integral = cdf[n];
+        if integral > 0.0 {
+            for v in cdf.iter_mut() {
+                *v /= integral;
+            }
+        } else {
+            // 全是0的话就当成均匀分布
+            for (i, v) in cdf.iter_mut().enumerate() {
+                *v = i as f64 / n as f64;
+            }
+        }
+
+        return Ok(Self {
+            function: function,
+            cdf: cdf,
+            integral: integral,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        return self.function.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.function.is_empty();
+    }
+
+    pub fn function(&self) -> &Vec<f64> {
+        return &self.function;
+    }
+
+    pub fn integral(&self) -> f64 {
+        return self.integral;
+    }
+
+    // 用[0, 1)上均匀的u取一个[0, 1)上的点，返回这个点、它的pdf、它落在第几段
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        // 找最后一个cdf[i] <= u的i
+        let mut left = 0;
+        let mut right = self.len();
+        while right - left > 1 {
+            let middle = (left + right) / 2;
+            if self.cdf[middle] <= u {
+                left = middle;
+            } else {
+                right = middle;
+            }
+        }
+
+        let width = self.cdf[left + 1] - self.cdf[left];
+        let offset = if width > 0.0 {
+            (u - self.cdf[left]) / width
+        } else {
+            0.0
+        };
+        let x = ((left as f64 + offset) / self.len() as f64).min(1.0 - 1e-12);
+        return (x, self.pdf(x), left);
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        let n = self.len();
+        let i = ((x * n as f64) as usize).min(n - 1);
+
+        if self.integral > 0.0 {
+            return self.function[i].max(0.0) / self.integral;
+        } else {
+            return 1.0;
+        }
+    }
+}
+
+// 二维的分段常数分布，先按每一行的积分选一行，再在这一行里选一列
+#[derive(Clone, Debug)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>, // 每一行一个
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    // function[j][i]，j是行（v方向），i是列（u方向）
+    // 没有行或者有空行都会报错
+    pub fn new(function: Vec<Vec<f64>>) -> Result<Self> {
+        let conditional = function
+            .into_iter()
+            .map(Distribution1D::new)
+            .collect::<Result<Vec<Distribution1D>>>()?;
+        let marginal = Distribution1D::new(conditional.iter().map(|v| v.integral()).collect())?;
+
+        return Ok(Self {
+            conditional: conditional,
+            marginal: marginal,
+        });
+    }
+
+    pub fn integral(&self) -> f64 {
+        return self.marginal.integral();
+    }
+
+    // 返回(u, v)和它在[0, 1]^2上的pdf
+    pub fn sample(&self, sample: (f64, f64)) -> ((f64, f64), f64) {
+        let (v, vPdf, row) = self.marginal.sample(sample.1);
+        let (u, uPdf, _) = self.conditional[row].sample(sample.0);
+        return ((u, v), uPdf * vPdf);
+    }
+
+    pub fn pdf(&self, uv: &(f64, f64)) -> f64 {
+        let n = self.marginal.len();
+        let row = ((uv.1 * n as f64) as usize).min(n - 1);
+        return self.marginal.pdf(uv.1) * self.conditional[row].pdf(uv.0);
+    }
+}
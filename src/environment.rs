@@ -0,0 +1,262 @@
+use crate::distribution::Distribution2D;
+use crate::material::Texture;
+use crate::sampler::Sampler;
+use crate::vec3::Vec3;
+
+use std::f64::consts::PI;
+use std::fmt::Debug;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Arc;
+
+// 光线什么都没打到的时候，按光线方向返回远处照过来的光
+pub trait Environment: Send + Sync + Debug {
+    fn value(&self, direction: &Vec3) -> Vec3;
+
+    // 能不能按亮度随机取一个方向，返回方向和立体角pdf。默认不能，积分器就只靠BSDF采样碰到它
+    fn sample(&self, sampler: &mut dyn Sampler) -> Option<(Vec3, f64)> {
+        return None;
+    }
+
+    // sample()采到direction的概率密度
+    fn pdf(&self, direction: &Vec3) -> f64 {
+        return 0.0;
+    }
+}
+
+// 各个方向都一样，黑色背景也是这个
+#[derive(Clone, Debug)]
+pub struct ConstantEnvironment {
+    color: Vec3,
+}
+
+impl ConstantEnvironment {
+    pub fn new(color: Vec3) -> Self {
+        Self { color: color }
+    }
+
+    pub fn black() -> Self {
+        Self::new(Vec3::new(0.0, 0.0, 0.0))
+    }
+
+    pub fn color(&self) -> &Vec3 {
+        return &self.color;
+    }
+}
+
+impl Environment for ConstantEnvironment {
+    fn value(&self, direction: &Vec3) -> Vec3 {
+        return self.color;
+    }
+}
+
+// 就是原来render.rs里注释掉的那个天空，从正下方的bottom渐变到正上方的top
+#[derive(Clone, Debug)]
+pub struct GradientEnvironment {
+    bottom: Vec3,
+    top: Vec3,
+}
+
+impl GradientEnvironment {
+    pub fn new(bottom: Vec3, top: Vec3) -> Self {
+        Self {
+            bottom: bottom,
+            top: top,
+        }
+    }
+
+    pub fn bottom(&self) -> &Vec3 {
+        return &self.bottom;
+    }
+
+    pub fn top(&self) -> &Vec3 {
+        return &self.top;
+    }
+}
+
+impl Environment for GradientEnvironment {
+    fn value(&self, direction: &Vec3) -> Vec3 {
+        let t = 0.5 * (direction.normalized().y() + 1.0);
+        return self.bottom * (1.0 - t) + self.top * t;
+    }
+}
+
+// 经纬度展开的环境贴图，贴图本身还是用Texture表示，这样ImageTexture那一套可以直接拿来用
+#[derive(Clone, Debug)]
+pub struct EquirectangularEnvironment {
+    texture: Arc<dyn Texture>,
+    distribution: Option<Arc<Distribution2D>>, // 按亮度重要性采样用的
+}
+
+impl EquirectangularEnvironment {
+    pub fn new<T>(texture: T) -> Self
+    where
+        T: Into<Arc<dyn Texture>>,
+    {
+        Self {
+            texture: texture.into(),
+            distribution: None,
+        }
+    }
+
+    // 贴图里有个很亮的太阳的话，光靠BSDF采样很难碰到，噪点非常多
+    // 所以把贴图按width x height的格子取亮度，建一个二维分段常数分布，直接往亮的地方采样
+    // 每个格子还要乘上sin(theta)，因为越靠近两极，同样大小的格子对应的立体角越小
+    // 格子数是0的话建不了分布，返回InvalidInput
+    pub fn importanceSampled<T>(texture: T, width: usize, height: usize) -> Result<Self>
+    where
+        T: Into<Arc<dyn Texture>>,
+    {
+        if width == 0 || height == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "importance sampling needs a non-empty map",
+            ));
+        }
+        let texture = texture.into();
+        let mut function = vec![vec![0.0; width]; height];
+
+        for (j, row) in function.iter_mut().enumerate() {
+            let v = (j as f64 + 0.5) / height as f64;
+            let sinTheta = ((1.0 - v) * PI).sin();
+
+            for (i, value) in row.iter_mut().enumerate() {
+                let uv = ((i as f64 + 0.5) / width as f64, v);
+                let color = texture.value(&uv, &Self::uvToDirection(&uv));
+                *value = luminance(&color) * sinTheta;
+            }
+        }
+
+        return Ok(Self {
+            texture: texture,
+            distribution: Some(Arc::new(Distribution2D::new(function)?)),
+        });
+    }
+
+    pub fn texture(&self) -> &Arc<dyn Texture> {
+        return &self.texture;
+    }
+
+    pub fn distribution(&self) -> &Option<Arc<Distribution2D>> {
+        return &self.distribution;
+    }
+
+    // 和球面的uv是一样的，同一张地球贴图贴在球上和当成环境贴图方向是一致的
+    // u沿着赤道绕一圈，v = 0是正下方，v = 1是正上方
+    pub fn directionToUv(direction: &Vec3) -> (f64, f64) {
+        let direction = direction.normalized();
+        let u = 0.5 + (direction.x().atan2(direction.z())) / (2.0 * PI);
+        let v = 1.0 - direction.y().clamp(-1.0, 1.0).acos() / PI;
+        return (u, v);
+    }
+
+    pub fn uvToDirection(uv: &(f64, f64)) -> Vec3 {
+        let phi = (uv.0 - 0.5) * 2.0 * PI;
+        let theta = (1.0 - uv.1) * PI;
+        return Vec3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            theta.sin() * phi.cos(),
+        );
+    }
+}
+
+impl Environment for EquirectangularEnvironment {
+    fn value(&self, direction: &Vec3) -> Vec3 {
+        return self
+            .texture
+            .value(&Self::directionToUv(direction), &direction.normalized());
+    }
+
+    // uv平面上的pdf换成立体角pdf：dω = sin(theta) dtheta dphi = 2pi^2 sin(theta) du dv
+    fn sample(&self, sampler: &mut dyn Sampler) -> Option<(Vec3, f64)> {
+        let distribution = self.distribution.as_ref()?;
+        let (uv, uvPdf) = distribution.sample(sampler.next2D());
+
+        let sinTheta = ((1.0 - uv.1) * PI).sin();
+        if uvPdf <= 0.0 || sinTheta <= 0.0 {
+            return None;
+        }
+
+        return Some((Self::uvToDirection(&uv), uvPdf / (2.0 * PI * PI * sinTheta)));
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f64 {
+        if let Some(distribution) = &self.distribution {
+            let uv = Self::directionToUv(direction);
+            let sinTheta = ((1.0 - uv.1) * PI).sin();
+            if sinTheta <= 0.0 {
+                return 0.0;
+            }
+            return distribution.pdf(&uv) / (2.0 * PI * PI * sinTheta);
+        } else {
+            return 0.0;
+        }
+    }
+}
+
+// <https://en.wikipedia.org/wiki/Relative_luminance>
+pub fn luminance(color: &Vec3) -> f64 {
+    return 0.2126 * color.r() + 0.7152 * color.g() + 0.0722 * color.b();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::distribution::Distribution1D;
+    use crate::distribution::Distribution2D;
+    use crate::environment::Environment;
+    use crate::environment::EquirectangularEnvironment;
+    use crate::material::ImageTexture;
+    use crate::material::Texture;
+    use crate::sampler::RandomSampler;
+    use crate::util::randomUnitVector;
+    use crate::vec3::Vec3;
+
+    use std::f64::consts::PI;
+    use std::io::ErrorKind;
+    use std::sync::Arc;
+
+    // 天空上方有个亮块。pdf在整个球面上积分是1，sample()给的pdf和pdf()一致
+    #[test]
+    fn importanceSampledPdfIsConsistent() {
+        let texture = ImageTexture::new(|uv: &(f64, f64)| -> Vec3 {
+            if uv.0 > 0.6 && uv.0 < 0.7 && uv.1 > 0.7 && uv.1 < 0.8 {
+                return Vec3::new(20.0, 18.0, 15.0);
+            } else {
+                return Vec3::new(0.3, 0.4, 0.6);
+            }
+        });
+        let texture: Arc<dyn Texture> = Arc::new(texture);
+        let environment = EquirectangularEnvironment::importanceSampled(texture, 64, 32).unwrap();
+
+        let mut sampler = RandomSampler::new(0);
+        let n = 200000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            sum += environment.pdf(&randomUnitVector(&mut sampler));
+        }
+        let integral = sum / n as f64 * 4.0 * PI;
+        assert!((integral - 1.0).abs() < 0.05, "{}", integral);
+
+        for _ in 0..100 {
+            let (direction, pdf) = environment.sample(&mut sampler).unwrap();
+            let expected = environment.pdf(&direction);
+            assert!(
+                (pdf - expected).abs() < 1e-6 * expected,
+                "{} {}",
+                pdf,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn importanceSampledRejectsEmptyMap() {
+        let texture: Arc<dyn Texture> = Arc::new(ImageTexture::new(|_: &(f64, f64)| -> Vec3 {
+            return Vec3::new(1.0, 1.0, 1.0);
+        }));
+        let error = EquirectangularEnvironment::importanceSampled(texture, 0, 0).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(Distribution1D::new(vec![]).is_err());
+        assert!(Distribution2D::new(vec![vec![1.0], vec![]]).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use crate::tonemap::DisplayTransform;
+use crate::vec3::Vec3;
+
+use std::collections::BTreeMap;
+use std::io::Result;
+use std::io::Write;
+
+// 渲染结果，存的是线性的radiance，不做任何裁剪和gamma，合成软件拿到的就是原始的数值
+// 和原来的buffer一样，(x, y)里y = 0是画面最下面一行
+#[derive(Clone, Debug, PartialEq)]
+pub struct Film {
+    width: usize,
+    height: usize,
+    pixels: Vec<[f32; 3]>,        // 一行一行存，从最下面一行开始
+    aovs: BTreeMap<String, Film>, // 和颜色一起输出的深度、法向量之类的，按名字存
+}
+
+// OpenEXR里每个通道存成半精度还是单精度浮点数
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExrPixelType {
+    Half,
+    Float,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width: width,
+            height: height,
+            pixels: vec![[0.0; 3]; width * height],
+            aovs: BTreeMap::new(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        return self.width;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.height;
+    }
+
+    pub fn pixels(&self) -> &Vec<[f32; 3]> {
+        return &self.pixels;
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Vec3 {
+        let [r, g, b] = self.pixels[y * self.width + x];
+        return Vec3::new(r as f64, g as f64, b as f64);
+    }
+
+    pub fn setPixel(&mut self, x: usize, y: usize, color: &Vec3) {
+        self.pixels[y * self.width + x] = [color.r() as f32, color.g() as f32, color.b() as f32];
+    }
+
+    // 从(x, y)开始的width x height一块，AOV也一起裁
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Film {
+        let mut res = Film::new(width, height);
+        for j in 0..height {
+            for i in 0..width {
+                res.setPixel(i, j, &self.pixel(x + i, y + j));
+            }
+        }
+        for (name, aov) in self.aovs.iter() {
+            res.setAov(name, aov.crop(x, y, width, height));
+        }
+        return res;
+    }
+
+    pub fn aovs(&self) -> &BTreeMap<String, Film> {
+        return &self.aovs;
+    }
+
+    pub fn aov(&self, name: &str) -> Option<&Film> {
+        return self.aovs.get(name);
+    }
+
+    pub fn setAov(&mut self, name: &str, film: Film) {
+        self.aovs.insert(name.to_string(), film);
+    }
+
+    // 8位的sRGB图像，从最上面一行开始，每个pixel依次是R、G、B，可以直接交给image库
+    pub fn toSrgb8(&self, transform: &DisplayTransform) -> Vec<u8> {
+        let mut res = Vec::with_capacity(self.width * self.height * 3);
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                res.extend_from_slice(&transform.apply8(&self.pixel(x, y)));
+            }
+        }
+        return res;
+    }
+
+    // 文本格式的PPM，8位，经过transform转成sRGB
+    // <http://netpbm.sourceforge.net/doc/ppm.html>
+    pub fn writePpm(&self, writer: &mut dyn Write, transform: &DisplayTransform) -> Result<()> {
+        writeln!(writer, "P3")?;
+        writeln!(writer, "{} {}", self.width, self.height)?;
+        writeln!(writer, "255")?;
+
+        // PPM也是从最上面一行开始的
+        for pixel in self.toSrgb8(transform).chunks(3) {
+            writeln!(writer, "{} {} {}", pixel[0], pixel[1], pixel[2])?;
+        }
+
+        return Ok(());
+    }
+
+    // PFM：每个通道一个little endian的32位浮点数，正好也是从最下面一行开始存的
+    // 比例那一行是负数表示little endian
+    // <http://www.pauldebevec.com/Research/HDR/PFM/>
+    pub fn writePfm(&self, writer: &mut dyn Write) -> Result<()> {
+        write!(writer, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+
+        for pixel in self.pixels.iter() {
+            for channel in pixel.iter() {
+                writer.write_all(&channel.to_le_bytes())?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    // 最简单的OpenEXR：单个part、按行存、不压缩，通道是B、G、R（文件里的通道要按名字排序）
+    // 每一行是一个block，block前面有一张表记录每个block在文件里的位置
+    // <https://openexr.com/en/latest/OpenEXRFileLayout.html>
+    pub fn writeExr(&self, writer: &mut dyn Write, pixelType: ExrPixelType) -> Result<()> {
+        let (typeId, channelSize): (i32, usize) = match pixelType {
+            ExrPixelType::Half => (1, 2),
+            ExrPixelType::Float => (2, 4),
+        };
+
+        let mut header = vec![];
+        header.extend_from_slice(&0x01312f76_u32.to_le_bytes()); // magic number
+        header.extend_from_slice(&2_u32.to_le_bytes()); // 版本2，scanline
+
+        let mut channels = vec![];
+        for name in ["B", "G", "R"].iter() {
+            channels.extend_from_slice(name.as_bytes());
+            channels.push(0);
+            channels.extend_from_slice(&typeId.to_le_bytes());
+            channels.extend_from_slice(&[0, 0, 0, 0]); // pLinear和3个保留字节
+            channels.extend_from_slice(&1_i32.to_le_bytes()); // xSampling
+            channels.extend_from_slice(&1_i32.to_le_bytes()); // ySampling
+        }
+        channels.push(0);
+        exrAttribute(&mut header, "channels", "chlist", &channels);
+
+        exrAttribute(&mut header, "compression", "compression", &[0]); // 不压缩
+
+        let mut window = vec![];
+        for value in [0, 0, self.width as i32 - 1, self.height as i32 - 1].iter() {
+            window.extend_from_slice(&value.to_le_bytes());
+        }
+        exrAttribute(&mut header, "dataWindow", "box2i", &window);
+        exrAttribute(&mut header, "displayWindow", "box2i", &window);
+
+        exrAttribute(&mut header, "lineOrder", "lineOrder", &[0]); // 从上往下
+        exrAttribute(
+            &mut header,
+            "pixelAspectRatio",
+            "float",
+            &1.0_f32.to_le_bytes(),
+        );
+        exrAttribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        exrAttribute(
+            &mut header,
+            "screenWindowWidth",
+            "float",
+            &1.0_f32.to_le_bytes(),
+        );
+        header.push(0); // header结束
+
+        writer.write_all(&header)?;
+
+        // offset table，每个block的位置是从文件开头算的
+        let blockSize = 8 + self.width * 3 * channelSize;
+        let start = header.len() + self.height * 8;
+        for i in 0..self.height {
+            writer.write_all(&((start + i * blockSize) as u64).to_le_bytes())?;
+        }
+
+        // EXR的y是往下的，第0行是画面最上面一行
+        for row in 0..self.height {
+            let y = self.height - 1 - row;
+            let mut block = Vec::with_capacity(blockSize);
+            block.extend_from_slice(&(row as i32).to_le_bytes());
+            block.extend_from_slice(&((blockSize - 8) as i32).to_le_bytes());
+
+            // 一行里面先是所有pixel的B，再是所有pixel的G，最后是R
+            for channel in (0..3).rev() {
+                for x in 0..self.width {
+                    let value = self.pixels[y * self.width + x][channel];
+                    match pixelType {
+                        ExrPixelType::Half => {
+                            block.extend_from_slice(&floatToHalf(value).to_le_bytes())
+                        }
+                        ExrPixelType::Float => block.extend_from_slice(&value.to_le_bytes()),
+                    }
+                }
+            }
+
+            writer.write_all(&block)?;
+        }
+
+        return Ok(());
+    }
+}
+
+impl From<&Vec<Vec<Vec3>>> for Film {
+    fn from(buffer: &Vec<Vec<Vec3>>) -> Self {
+        let height = buffer.len();
+        let width = buffer.first().map(|row| row.len()).unwrap_or(0);
+        let mut res = Self::new(width, height);
+
+        for (y, row) in buffer.iter().enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                res.setPixel(x, y, pixel);
+            }
+        }
+
+        return res;
+    }
+}
+
+// 属性是：名字、类型名（都以0结尾）、值的字节数、值
+fn exrAttribute(header: &mut Vec<u8>, name: &str, typeName: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(typeName.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+// 32位浮点数转成IEEE 754半精度，就近舍入，太大的变成无穷，太小的变成非规格化数或者0
+pub fn floatToHalf(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7fffff;
+
+    if exponent == 0xff {
+        // 无穷或者NaN，NaN要保证尾数不是0
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+
+    let exponent = exponent - 127 + 15;
+
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        // 非规格化数，把隐含的1补上再右移
+        let mantissa = mantissa | 0x800000;
+        let shift = (14 - exponent) as u32;
+        let half = mantissa >> shift;
+        let remainder = mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let rounded = if remainder > halfway || (remainder == halfway && half & 1 == 1) {
+            half + 1
+        } else {
+            half
+        };
+        return sign | rounded as u16;
+    }
+
+    let half = ((exponent as u32) << 10) | (mantissa >> 13);
+    let remainder = mantissa & 0x1fff;
+    // 进位进到指数里也是对的，最大的数会正好变成无穷
+    let rounded = if remainder > 0x1000 || (remainder == 0x1000 && half & 1 == 1) {
+        half + 1
+    } else {
+        half
+    };
+    return sign | rounded as u16;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::film::floatToHalf;
+    use crate::film::ExrPixelType;
+    use crate::film::Film;
+    use crate::vec3::Vec3;
+
+    use std::convert::TryInto;
+
+    #[test]
+    fn halfConversion() {
+        assert_eq!(floatToHalf(0.0), 0x0000);
+        assert_eq!(floatToHalf(-0.0), 0x8000);
+        assert_eq!(floatToHalf(1.0), 0x3c00);
+        assert_eq!(floatToHalf(-2.0), 0xc000);
+        assert_eq!(floatToHalf(0.5), 0x3800);
+        assert_eq!(floatToHalf(65504.0), 0x7bff); // 半精度最大的数
+        assert_eq!(floatToHalf(1e6), 0x7c00);
+        assert_eq!(floatToHalf(5.960464477539063e-8), 0x0001); // 最小的非规格化数
+        assert_eq!(floatToHalf(1.0 + 1.0 / 2048.0), 0x3c00); // 正好一半，舍入到偶数
+    }
+
+    // 头部和offset table之后正好是每行一个block，文件长度能对上
+    #[test]
+    fn exrLayout() {
+        let mut film = Film::new(3, 2);
+        film.setPixel(1, 0, &Vec3::new(4.0, 2.0, 1.0));
+
+        for (pixelType, channelSize) in [(ExrPixelType::Half, 2), (ExrPixelType::Float, 4)].iter() {
+            let mut bytes = vec![];
+            film.writeExr(&mut bytes, *pixelType).unwrap();
+            assert_eq!(&bytes[0..4], &[0x76, 0x2f, 0x31, 0x01]);
+
+            let blockSize = 8 + 3 * 3 * channelSize;
+            let table = bytes.len() - 2 * blockSize - 2 * 8;
+            let first = u64::from_le_bytes(bytes[table..table + 8].try_into().unwrap());
+            assert_eq!(first as usize, bytes.len() - 2 * blockSize);
+
+            // 画面最下面一行是EXR的第1行，R通道在B、G后面
+            let block = &bytes[bytes.len() - blockSize..];
+            assert_eq!(&block[0..4], &1_i32.to_le_bytes());
+            let r = &block[8 + 2 * 3 * channelSize + channelSize..][..*channelSize];
+            if *pixelType == ExrPixelType::Half {
+                assert_eq!(r, &floatToHalf(4.0).to_le_bytes());
+            } else {
+                assert_eq!(r, &4.0_f32.to_le_bytes());
+            }
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::f64::consts::PI;
+use std::fmt::Debug;
+
+// 像素重建滤波器：一个样本落在画面上(x, y)的位置，对中心在(px + 0.5, py + 0.5)的pixel的贡献权重是evaluate(x - px - 0.5, y - py - 0.5)
+// 每个pixel最后的颜色是所有样本颜色按权重的加权平均
+// 超过radius的样本对这个pixel没有贡献。radius大于0.5的话，一个样本会影响到周围的pixel
+// <http://www.pbr-book.org/3ed-2018/Sampling_and_Reconstruction/Image_Reconstruction.html>
+pub trait Filter: Send + Sync + Debug {
+    fn radius(&self) -> f64;
+
+    fn evaluate(&self, x: f64, y: f64) -> f64;
+}
+
+// 原来的做法：每个样本只算到自己所在的pixel里，所有样本权重一样
+#[derive(Clone, Debug)]
+pub struct BoxFilter {
+    radius: f64,
+}
+
+impl BoxFilter {
+    pub fn new(radius: f64) -> Self {
+        Self { radius: radius }
+    }
+}
+
+impl Default for BoxFilter {
+    fn default() -> Self {
+        Self::new(0.5)
+    }
+}
+
+impl Filter for BoxFilter {
+    fn radius(&self) -> f64 {
+        return self.radius;
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        if x.abs() <= self.radius && y.abs() <= self.radius {
+            return 1.0;
+        } else {
+            return 0.0;
+        }
+    }
+}
+
+// 离中心越远权重线性减小，到radius的时候是0
+#[derive(Clone, Debug)]
+pub struct TentFilter {
+    radius: f64,
+}
+
+impl TentFilter {
+    pub fn new(radius: f64) -> Self {
+        Self { radius: radius }
+    }
+}
+
+impl Default for TentFilter {
+    fn default() -> Self {
+        Self::new(1.0)
+    }
+}
+
+impl Filter for TentFilter {
+    fn radius(&self) -> f64 {
+        return self.radius;
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        return (self.radius - x.abs()).max(0.0) * (self.radius - y.abs()).max(0.0);
+    }
+}
+
+// 高斯函数减去它在radius处的值，这样到radius的时候正好降到0，不会有一圈突变
+#[derive(Clone, Debug)]
+pub struct GaussianFilter {
+    radius: f64,
+    sigma: f64,
+}
+
+impl GaussianFilter {
+    pub fn new(radius: f64, sigma: f64) -> Self {
+        Self {
+            radius: radius,
+            sigma: sigma,
+        }
+    }
+
+    pub fn sigma(&self) -> f64 {
+        return self.sigma;
+    }
+
+    fn gaussian(&self, x: f64) -> f64 {
+        let edge = (-self.radius * self.radius / (2.0 * self.sigma * self.sigma)).exp();
+        return ((-x * x / (2.0 * self.sigma * self.sigma)).exp() - edge).max(0.0);
+    }
+}
+
+impl Default for GaussianFilter {
+    fn default() -> Self {
+        Self::new(1.5, 0.5)
+    }
+}
+
+impl Filter for GaussianFilter {
+    fn radius(&self) -> f64 {
+        return self.radius;
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        return self.gaussian(x) * self.gaussian(y);
+    }
+}
+
+// Mitchell-Netravali三次样条，b和c推荐取1/3，边缘附近有一点负的权重，看起来比高斯更锐利
+// <https://www.cs.utexas.edu/~fussell/courses/cs384g-fall2013/lectures/mitchell/Mitchell.pdf>
+#[derive(Clone, Debug)]
+pub struct MitchellFilter {
+    radius: f64,
+    b: f64,
+    c: f64,
+}
+
+impl MitchellFilter {
+    pub fn new(radius: f64, b: f64, c: f64) -> Self {
+        Self {
+            radius: radius,
+            b: b,
+            c: c,
+        }
+    }
+
+    pub fn b(&self) -> f64 {
+        return self.b;
+    }
+
+    pub fn c(&self) -> f64 {
+        return self.c;
+    }
+
+    // 定义在[-2, 2]上
+    fn mitchell(&self, x: f64) -> f64 {
+        let (b, c) = (self.b, self.c);
+        let x = x.abs();
+
+        if x < 1.0 {
+            return ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                + (6.0 - 2.0 * b))
+                / 6.0;
+        } else if x < 2.0 {
+            return ((-b - 6.0 * c) * x * x * x
+                + (6.0 * b + 30.0 * c) * x * x
+                + (-12.0 * b - 48.0 * c) * x
+                + (8.0 * b + 24.0 * c))
+                / 6.0;
+        } else {
+            return 0.0;
+        }
+    }
+}
+
+impl Default for MitchellFilter {
+    fn default() -> Self {
+        Self::new(2.0, 1.0 / 3.0, 1.0 / 3.0)
+    }
+}
+
+impl Filter for MitchellFilter {
+    fn radius(&self) -> f64 {
+        return self.radius;
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        return self.mitchell(2.0 * x / self.radius) * self.mitchell(2.0 * y / self.radius);
+    }
+}
+
+// 用sinc(x / radius)做窗口的sinc函数，radius就是保留几个瓣
+#[derive(Clone, Debug)]
+pub struct LanczosFilter {
+    radius: f64,
+}
+
+impl LanczosFilter {
+    pub fn new(radius: f64) -> Self {
+        Self { radius: radius }
+    }
+
+    fn lanczos(&self, x: f64) -> f64 {
+        if x.abs() >= self.radius {
+            return 0.0;
+        }
+        return sinc(x) * sinc(x / self.radius);
+    }
+}
+
+impl Default for LanczosFilter {
+    fn default() -> Self {
+        Self::new(3.0)
+    }
+}
+
+impl Filter for LanczosFilter {
+    fn radius(&self) -> f64 {
+        return self.radius;
+    }
+
+    fn evaluate(&self, x: f64, y: f64) -> f64 {
+        return self.lanczos(x) * self.lanczos(y);
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    return (PI * x).sin() / (PI * x);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::BoxFilter;
+    use crate::filter::Filter;
+    use crate::filter::GaussianFilter;
+    use crate::filter::LanczosFilter;
+    use crate::filter::MitchellFilter;
+    use crate::filter::TentFilter;
+
+    // 中心权重最大，对称，到radius外面就是0
+    #[test]
+    fn filtersVanishOutsideRadius() {
+        let filters: Vec<Box<dyn Filter>> = vec![
+            Box::new(BoxFilter::default()),
+            Box::new(TentFilter::default()),
+            Box::new(GaussianFilter::default()),
+            Box::new(MitchellFilter::default()),
+            Box::new(LanczosFilter::default()),
+        ];
+
+        for filter in filters.iter() {
+            let radius = filter.radius();
+            let center = filter.evaluate(0.0, 0.0);
+            assert!(center > 0.0);
+
+            for i in 0..=20 {
+                let x = radius * i as f64 / 20.0;
+                assert!(filter.evaluate(x, 0.3 * radius) <= center);
+                assert_eq!(filter.evaluate(x, 0.3), filter.evaluate(-x, -0.3));
+            }
+
+            assert_eq!(filter.evaluate(radius * 1.01, 0.0), 0.0);
+            assert_eq!(filter.evaluate(0.0, radius * 1.01), 0.0);
+        }
+    }
+}
@@ -1,10 +1,13 @@
+use crate::mat4::AnimatedTransform;
 use crate::mat4::Mat4;
 use crate::mat4::Mat4Cached;
 use crate::ray::Hit;
 use crate::ray::HitRecord;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::vec3::Vec3;
 
+use std::borrow::Cow;
 use std::f64::consts::PI;
 use std::sync::Arc;
 
@@ -40,7 +43,7 @@ impl Sphere {
 }
 
 impl Hit for Sphere {
-    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<HitRecord> {
         let center = Vec3::new(0.0, 0.0, 0.0);
         let oc = *ray.origin() - center;
         let a = ray.direction().dot(ray.direction());
@@ -74,11 +77,11 @@ impl Hit for Sphere {
 }
 
 impl Hit for Vec<Box<dyn Hit>> {
-    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<HitRecord> {
         let mut res = None;
 
         for v in self.iter() {
-            if let Some(record) = v.hit(ray) {
+            if let Some(record) = v.hit(ray, sampler) {
                 if res.is_none() {
                     res.replace(record);
                 } else {
@@ -96,11 +99,11 @@ impl Hit for Vec<Box<dyn Hit>> {
 // 难道还要给Vec<Arc<dyn Hit>>写一遍吗？能不能一次impl同时给Vec<Box<dyn Hit>>和Vec<Arc<dyn Hit>>实现呢？他们的代码真的没有任何区别
 
 impl Hit for Vec<Arc<dyn Hit>> {
-    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<HitRecord> {
         let mut res = None;
 
         for v in self.iter() {
-            if let Some(record) = v.hit(ray) {
+            if let Some(record) = v.hit(ray, sampler) {
                 if res.is_none() {
                     res.replace(record);
                 } else {
@@ -150,7 +153,7 @@ impl Rectangle {
 }
 
 impl Hit for Rectangle {
-    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<HitRecord> {
         let z = 0.0;
         let a = (-self.width / 2.0, -self.height / 2.0);
         let b = (self.width / 2.0, self.height / 2.0);
@@ -185,6 +188,7 @@ impl Hit for Rectangle {
 pub struct TransformedGeometry<T> {
     geometry: T, // geometry应不应该是Arc<T>呢？因为这个可以看作是对原来geometry的修饰
     transform: Mat4Cached,
+    motion: Option<AnimatedTransform>, // 有的话按光线的时间取变换，transform就不用了
 }
 
 impl<T> TransformedGeometry<T> {
@@ -195,6 +199,16 @@ impl<T> TransformedGeometry<T> {
         Self {
             geometry: geometry,
             transform: transform.into(),
+            motion: None,
+        }
+    }
+
+    // 动起来的几何体，见AnimatedTransform
+    pub fn animated(geometry: T, motion: AnimatedTransform) -> Self {
+        Self {
+            geometry: geometry,
+            transform: motion.keyframes()[0].1,
+            motion: Some(motion),
         }
     }
 
@@ -202,30 +216,42 @@ impl<T> TransformedGeometry<T> {
         return &self.geometry;
     }
 
+    // 动起来的话是第一个关键帧的变换
     pub fn transform(&self) -> &Mat4Cached {
         return &self.transform;
     }
+
+    pub fn motion(&self) -> &Option<AnimatedTransform> {
+        return &self.motion;
+    }
+
+    pub fn transformAt(&self, time: f64) -> Cow<'_, Mat4Cached> {
+        match &self.motion {
+            Some(motion) => Cow::Owned(motion.at(time)),
+            None => Cow::Borrowed(&self.transform),
+        }
+    }
 }
 
 impl<T> Hit for TransformedGeometry<T>
 where
     T: Hit,
 {
-    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<HitRecord> {
         let geometry = self.geometry();
-        let transform = self.transform();
+        let transform = self.transformAt(ray.time());
 
         if let Some(inversed) = &transform.inversed() {
             // 原光线反变换
             let origin = ray.origin().xyz1().transformed(inversed);
             let direction = ray.direction().xyz0().transformed(inversed);
 
-            let ray = Ray::new(origin.into(), direction.into());
+            let ray = Ray::new(origin.into(), direction.into()).withTime(ray.time());
 
-            if let Some(record) = geometry.hit(&ray) {
+            if let Some(record) = geometry.hit(&ray, sampler) {
                 // 击中后再正变换
-                let intersection = record.intersection().xyz1().transformed(transform.as_ref());
-                let normal = record.normal().xyz0().transformed(transform.as_ref());
+                let intersection = record.intersection().xyz1().transformed(transform.origin());
+                let normal = record.normal().xyz0().transformed(transform.origin());
 
                 let res = HitRecord::new(
                     record.t(),
@@ -234,7 +260,7 @@ where
                     None,
                     *record.uv(),
                 );
-                return Some(res);
+                return Some(res.withObjectId(record.objectId()));
             } else {
                 return None;
             }
@@ -0,0 +1,553 @@
+use crate::environment::Environment;
+use crate::light::Sample;
+use crate::material::Material;
+use crate::ray::Hit;
+use crate::ray::HitRecord;
+use crate::ray::Ray;
+use crate::render::colorInEnvironment;
+use crate::sampler::Sampler;
+use crate::vec3::Vec3;
+
+use std::sync::Arc;
+
+// 积分器：给一条从相机出发的光线，估计它带回来的radiance
+// 用到的随机数都从sampler里拿
+pub trait Integrator: Send + Sync {
+    // 除了radiance还返回相机光线第一次打到的地方，AOV就用它，不用再单独求一次交
+    // 烟雾之类的求交也要随机数，这样AOV看到的一定是算颜色的时候打到的那个点
+    fn radianceAndFirstHit<'a>(
+        &self,
+        ray: &Ray,
+        world: &'a dyn Hit,
+        environment: &dyn Environment,
+        maxDepth: usize,
+        sampler: &mut dyn Sampler,
+    ) -> (Vec3, Option<HitRecord<'a>>);
+
+    fn radiance(
+        &self,
+        ray: &Ray,
+        world: &dyn Hit,
+        environment: &dyn Environment,
+        maxDepth: usize,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        return self
+            .radianceAndFirstHit(ray, world, environment, maxDepth, sampler)
+            .0;
+    }
+
+    // 具体是哪一种积分器，checkpoint和分布式渲染的时候用来对一下设置
+    fn name(&self) -> &'static str {
+        return std::any::type_name::<Self>();
+    }
+}
+
+// 原来的递归版本，就是render::colorInEnvironment
+#[derive(Clone, Debug)]
+pub struct Recursive;
+
+impl Integrator for Recursive {
+    // 第一层在这里求交，记下打到的地方，后面的弹射还是交给colorInEnvironment
+    fn radianceAndFirstHit<'a>(
+        &self,
+        ray: &Ray,
+        world: &'a dyn Hit,
+        environment: &dyn Environment,
+        maxDepth: usize,
+        sampler: &mut dyn Sampler,
+    ) -> (Vec3, Option<HitRecord<'a>>) {
+        if maxDepth == 0 {
+            return (Vec3::new(0.0, 0.0, 0.0), None);
+        }
+
+        let record = match world.hit(ray, sampler) {
+            Some(record) => record,
+            None => return (environment.value(ray.direction()), None),
+        };
+        let color = match record.material() {
+            Some(material) => match material.scatter(ray, &record, sampler) {
+                Some((scattered, attenuation)) => {
+                    attenuation
+                        * colorInEnvironment(&scattered, world, environment, maxDepth - 1, sampler)
+                        + material.emitted(record.uv(), record.intersection())
+                }
+                None => material.emitted(record.uv(), record.intersection()),
+            },
+            None => Vec3::new(0.0, 0.0, 0.0),
+        };
+        return (color, Some(record));
+    }
+}
+
+// color()每弹射一次递归一层，maxDepth = 100的时候栈会爆，所以改成循环
+// 循环里记录当前路径的throughput（之前所有attenuation的乘积），弹射次数超过rouletteDepth之后开始俄罗斯轮盘赌：
+// 以throughput最大分量为概率继续，否则直接结束这条路径。继续的话throughput要除以这个概率，这样期望值和color()是一样的
+#[derive(Clone, Debug)]
+pub struct PathTracer {
+    rouletteDepth: usize,
+}
+
+impl PathTracer {
+    pub fn new(rouletteDepth: usize) -> Self {
+        Self {
+            rouletteDepth: rouletteDepth,
+        }
+    }
+
+    pub fn rouletteDepth(&self) -> usize {
+        return self.rouletteDepth;
+    }
+}
+
+impl Default for PathTracer {
+    fn default() -> Self {
+        Self::new(5)
+    }
+}
+
+impl Integrator for PathTracer {
+    fn radianceAndFirstHit<'a>(
+        &self,
+        ray: &Ray,
+        world: &'a dyn Hit,
+        environment: &dyn Environment,
+        maxDepth: usize,
+        sampler: &mut dyn Sampler,
+    ) -> (Vec3, Option<HitRecord<'a>>) {
+        let mut res = Vec3::new(0.0, 0.0, 0.0);
+        let mut firstHit = None;
+        let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+
+        for depth in 0..maxDepth {
+            let record = match world.hit(&ray, sampler) {
+                Some(record) => record,
+                None => {
+                    res += throughput * environment.value(ray.direction());
+                    break;
+                }
+            };
+            if depth == 0 {
+                firstHit = Some(record.clone());
+            }
+            let material = match record.material() {
+                Some(material) => material,
+                None => break,
+            };
+
+            res += throughput * material.emitted(record.uv(), record.intersection());
+
+            if let Some((scattered, attenuation)) = material.scatter(&ray, &record, sampler) {
+                throughput = throughput * attenuation;
+
+                if depth >= self.rouletteDepth {
+                    let probability = throughput
+                        .r()
+                        .max(throughput.g())
+                        .max(throughput.b())
+                        .min(1.0);
+                    if probability <= 0.0 || sampler.next1D() >= probability {
+                        break;
+                    }
+                    throughput /= probability;
+                }
+
+                ray = scattered;
+            } else {
+                break;
+            }
+        }
+
+        return (res, firstHit);
+    }
+}
+
+// 光线只靠随机弹射碰到灯的话，灯越小越难碰到，Cornell box那盏小灯1000 spp还是一堆噪点
+// 所以在每个漫反射的点上再主动往灯上随机取一点，连一条shadow ray过去看看有没有被挡住（next event estimation）
+// 这样同一条光路可能被两种方式采到：一种是BSDF采样弹射到灯上，一种是直接采样灯。两种都算上的话就重复了，
+// 所以用multiple importance sampling的power heuristic给两种方式分配权重，哪种方式采到这个方向的概率大，哪种方式的权重就大
+#[derive(Clone)]
+pub struct LightSamplingPathTracer {
+    lights: Vec<Arc<dyn Sample>>,
+    rouletteDepth: usize,
+}
+
+impl LightSamplingPathTracer {
+    pub fn new(lights: Vec<Arc<dyn Sample>>, rouletteDepth: usize) -> Self {
+        Self {
+            lights: lights,
+            rouletteDepth: rouletteDepth,
+        }
+    }
+
+    pub fn lights(&self) -> &Vec<Arc<dyn Sample>> {
+        return &self.lights;
+    }
+
+    pub fn rouletteDepth(&self) -> usize {
+        return self.rouletteDepth;
+    }
+
+    // 每盏灯被选中的概率一样，所以直接采样灯这种方式采到direction的概率密度是所有灯pdf的平均值
+    fn lightPdf(
+        &self,
+        reference: &Vec3,
+        direction: &Vec3,
+        time: f64,
+        sampler: &mut dyn Sampler,
+    ) -> f64 {
+        if self.lights.is_empty() {
+            return 0.0;
+        }
+
+        let mut res = 0.0;
+        for light in self.lights.iter() {
+            res += light.pdf(reference, direction, time, sampler);
+        }
+        return res / self.lights.len() as f64;
+    }
+
+    // 随机挑一盏灯、在灯上随机取一点，返回这一点照过来的光乘上BSDF和MIS权重
+    fn sampleLight(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        material: &dyn Material,
+        world: &dyn Hit,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        let black = Vec3::new(0.0, 0.0, 0.0);
+
+        if self.lights.is_empty() {
+            return black;
+        }
+
+        let index =
+            ((sampler.next1D() * self.lights.len() as f64) as usize).min(self.lights.len() - 1);
+        let (point, _, _) =
+            match self.lights[index].sample(record.intersection(), ray.time(), sampler) {
+                Some(v) => v,
+                None => return black,
+            };
+
+        let direction = point - *record.intersection();
+        let distance = direction.length();
+        let direction = direction / distance;
+
+        let bsdf = material.bsdf(ray, record, &direction);
+        if bsdf.r() <= 0.0 && bsdf.g() <= 0.0 && bsdf.b() <= 0.0 {
+            return black; // 背面，不用浪费一条shadow ray
+        }
+
+        // shadow ray，第一个碰到的就是灯上那个点才说明没被挡住
+        let shadow = Ray::new(*record.intersection(), direction).withTime(ray.time());
+        if let Some(lightRecord) = world.hit(&shadow, sampler) {
+            if (lightRecord.t() - distance).abs() > 1e-4 * distance {
+                return black;
+            }
+
+            if let Some(lightMaterial) = lightRecord.material() {
+                let emitted = lightMaterial.emitted(lightRecord.uv(), lightRecord.intersection());
+                let lightPdf =
+                    self.lightPdf(record.intersection(), &direction, ray.time(), sampler);
+                if lightPdf <= 0.0 {
+                    return black;
+                }
+                let bsdfPdf = material.pdf(ray, record, &direction);
+                let weight = powerHeuristic(lightPdf, bsdfPdf);
+                return emitted * bsdf * (weight / lightPdf);
+            }
+        }
+
+        return black;
+    }
+
+    // 环境贴图能按亮度采样的话也当成一盏无限远的灯，shadow ray什么都没打到才算照得到
+    fn sampleEnvironment(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        material: &dyn Material,
+        world: &dyn Hit,
+        environment: &dyn Environment,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        let black = Vec3::new(0.0, 0.0, 0.0);
+
+        let (direction, environmentPdf) = match environment.sample(sampler) {
+            Some(v) => v,
+            None => return black,
+        };
+        if environmentPdf <= 0.0 {
+            return black;
+        }
+
+        let bsdf = material.bsdf(ray, record, &direction);
+        if bsdf.r() <= 0.0 && bsdf.g() <= 0.0 && bsdf.b() <= 0.0 {
+            return black;
+        }
+
+        if world
+            .hit(
+                &Ray::new(*record.intersection(), direction).withTime(ray.time()),
+                sampler,
+            )
+            .is_some()
+        {
+            return black;
+        }
+
+        let bsdfPdf = material.pdf(ray, record, &direction);
+        let weight = powerHeuristic(environmentPdf, bsdfPdf);
+        return environment.value(&direction) * bsdf * (weight / environmentPdf);
+    }
+}
+
+fn powerHeuristic(pdf: f64, otherPdf: f64) -> f64 {
+    let a = pdf * pdf;
+    let b = otherPdf * otherPdf;
+    if a + b == 0.0 {
+        return 0.0;
+    }
+    return a / (a + b);
+}
+
+impl Integrator for LightSamplingPathTracer {
+    fn radianceAndFirstHit<'a>(
+        &self,
+        ray: &Ray,
+        world: &'a dyn Hit,
+        environment: &dyn Environment,
+        maxDepth: usize,
+        sampler: &mut dyn Sampler,
+    ) -> (Vec3, Option<HitRecord<'a>>) {
+        let mut res = Vec3::new(0.0, 0.0, 0.0);
+        let mut firstHit = None;
+        let mut throughput = Vec3::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+        // 上一个点的位置和BSDF采样的pdf。上一个点是相机或者镜面的话没有直接采样过灯，就是None
+        let mut previous: Option<(Vec3, f64)> = None;
+
+        for depth in 0..maxDepth {
+            let record = match world.hit(&ray, sampler) {
+                Some(record) => record,
+                None => {
+                    // 环境也可能被上一个点直接采样到，和灯一样要乘上权重
+                    let weight = match &previous {
+                        Some((_, bsdfPdf)) => {
+                            powerHeuristic(*bsdfPdf, environment.pdf(ray.direction()))
+                        }
+                        None => 1.0,
+                    };
+                    res += throughput * environment.value(ray.direction()) * weight;
+                    break;
+                }
+            };
+            if depth == 0 {
+                firstHit = Some(record.clone());
+            }
+            let material = match record.material() {
+                Some(material) => *material,
+                None => break,
+            };
+
+            // 弹射到灯上，这条光路上一个点也可能直接采样灯采到，要乘上BSDF采样这种方式的权重
+            let emitted = material.emitted(record.uv(), record.intersection());
+            let weight = match &previous {
+                Some((point, bsdfPdf)) => powerHeuristic(
+                    *bsdfPdf,
+                    self.lightPdf(point, ray.direction(), ray.time(), sampler),
+                ),
+                None => 1.0,
+            };
+            res += throughput * emitted * weight;
+
+            // 最后一个点就不要直接采样灯了，因为BSDF采样到了下一个点也不会再算了，两边权重对不上
+            if !material.isSpecular() && depth + 1 < maxDepth {
+                res += throughput * self.sampleLight(&ray, &record, material, world, sampler);
+                res += throughput
+                    * self.sampleEnvironment(&ray, &record, material, world, environment, sampler);
+            }
+
+            if let Some((scattered, attenuation)) = material.scatter(&ray, &record, sampler) {
+                previous = if material.isSpecular() {
+                    None
+                } else {
+                    Some((
+                        *record.intersection(),
+                        material.pdf(&ray, &record, scattered.direction()),
+                    ))
+                };
+                throughput = throughput * attenuation;
+
+                if depth >= self.rouletteDepth {
+                    let probability = throughput
+                        .r()
+                        .max(throughput.g())
+                        .max(throughput.b())
+                        .min(1.0);
+                    if probability <= 0.0 || sampler.next1D() >= probability {
+                        break;
+                    }
+                    throughput /= probability;
+                }
+
+                ray = scattered;
+            } else {
+                break;
+            }
+        }
+
+        return (res, firstHit);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::environment::ConstantEnvironment;
+    use crate::environment::Environment;
+    use crate::environment::GradientEnvironment;
+    use crate::geometry::Rectangle;
+    use crate::geometry::Sphere;
+    use crate::integrator::Integrator;
+    use crate::integrator::LightSamplingPathTracer;
+    use crate::integrator::PathTracer;
+    use crate::integrator::Recursive;
+    use crate::light::Sample;
+    use crate::mat4::Mat4;
+    use crate::material::DiffuseLight;
+    use crate::material::Lambertian;
+    use crate::material::Material;
+    use crate::optimize::AxisAlignedBoundingBox;
+    use crate::optimize::Bound;
+    use crate::ray::HitRecord;
+    use crate::ray::Ray;
+    use crate::sampler::RandomSampler;
+    use crate::sampler::Sampler;
+    use crate::sprite::Sprite;
+    use crate::util::randomInUnitSphere;
+    use crate::vec3::Vec3;
+
+    use std::sync::Arc;
+
+    // 又发光又以0.5的比例随机散射，在球里面看到的radiance是1 + 0.5 + 0.25 + ...
+    #[derive(Debug)]
+    struct Furnace;
+
+    impl Material for Furnace {
+        fn scatter(
+            &self,
+            _rayIn: &Ray,
+            hitRecord: &HitRecord,
+            sampler: &mut dyn Sampler,
+        ) -> Option<(Ray, Vec3)> {
+            // 球心在原点，单位球内随机一点减去交点，方向一定朝着球里面
+            let scattered = Ray::new(
+                *hitRecord.intersection(),
+                (randomInUnitSphere(sampler) - *hitRecord.intersection()).normalized(),
+            );
+            return Some((scattered, Vec3::new(0.5, 0.5, 0.5)));
+        }
+
+        fn emitted(&self, _uv: &(f64, f64), _point: &Vec3) -> Vec3 {
+            return Vec3::new(1.0, 1.0, 1.0);
+        }
+    }
+
+    #[test]
+    fn pathTracerMatchesRecursive() {
+        let world = Sprite::new(Some(Arc::new(Sphere::new(1.0))), Some(Arc::new(Furnace)));
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), Vec3::ex());
+        let maxDepth = 10;
+        let environment = ConstantEnvironment::black();
+        let mut sampler = RandomSampler::new(0);
+        let expected = Recursive
+            .radiance(&ray, &world, &environment, maxDepth, &mut sampler)
+            .r();
+        assert!((expected - 2.0 * (1.0 - 0.5_f64.powi(maxDepth as i32))).abs() < 1e-9);
+
+        let integrator = PathTracer::new(1);
+        let n = 20000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            sum += integrator
+                .radiance(&ray, &world, &environment, maxDepth, &mut sampler)
+                .r();
+        }
+        assert!((sum / n as f64 - expected).abs() < 0.05);
+    }
+
+    // 地板上方挂一盏小灯，直接采样灯加MIS和只靠BSDF采样的结果应该一样，只是噪点少很多
+    #[test]
+    fn lightSamplingMatchesPathTracer() {
+        let light = Arc::new(
+            Sprite::builder()
+                .geometry(Rectangle::new(1.0, 1.0).into())
+                .material(DiffuseLight::new(Vec3::new(4.0, 4.0, 4.0)).into())
+                .transform(Mat4::translation(Vec3::new(0.0, 0.0, 2.0)))
+                .build(),
+        );
+        let floor = Arc::new(
+            Sprite::builder()
+                .geometry(Rectangle::new(10.0, 10.0).into())
+                .material(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)).into())
+                .build(),
+        );
+        let world: Vec<Arc<dyn Bound<AxisAlignedBoundingBox>>> = vec![light.clone(), floor];
+        let lights: Vec<Arc<dyn Sample>> = vec![light];
+
+        let ray = Ray::new(
+            Vec3::new(0.0, -1.0, 1.0),
+            Vec3::new(0.3, 1.0, -1.0).normalized(),
+        );
+        let environment = ConstantEnvironment::black();
+        let mut sampler = RandomSampler::new(0);
+        let mut estimate = |integrator: &dyn Integrator, n: usize| -> f64 {
+            let mut sum = 0.0;
+            for _ in 0..n {
+                sum += integrator
+                    .radiance(&ray, &world, &environment, 3, &mut sampler)
+                    .r();
+            }
+            return sum / n as f64;
+        };
+
+        let expected = estimate(&PathTracer::new(10), 100000);
+        let actual = estimate(&LightSamplingPathTracer::new(lights, 10), 10000);
+        assert!(expected > 0.0);
+        assert!((actual - expected).abs() < 0.03 * expected);
+    }
+
+    // 什么都没打到的时候，每个积分器返回的都是环境在这个方向上的值
+    #[test]
+    fn missReturnsEnvironment() {
+        let world: Vec<Arc<dyn Bound<AxisAlignedBoundingBox>>> = vec![];
+        let constant = ConstantEnvironment::new(Vec3::new(0.2, 0.4, 0.6));
+        let gradient = GradientEnvironment::new(Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.5, 0.7, 1.0));
+        let environments: Vec<&dyn Environment> = vec![&constant, &gradient];
+        let integrators: Vec<Box<dyn Integrator>> = vec![
+            Box::new(Recursive),
+            Box::new(PathTracer::new(1)),
+            Box::new(LightSamplingPathTracer::new(vec![], 1)),
+        ];
+        let mut sampler = RandomSampler::new(0);
+
+        for direction in [
+            Vec3::ey(),
+            -Vec3::ey(),
+            Vec3::new(1.0, 0.5, -1.0).normalized(),
+        ]
+        .iter()
+        {
+            let ray = Ray::new(Vec3::new(0.0, 0.0, 0.0), *direction);
+            for environment in environments.iter() {
+                for integrator in integrators.iter() {
+                    let radiance = integrator.radiance(&ray, &world, *environment, 5, &mut sampler);
+                    assert!((radiance - environment.value(direction)).length() < 1e-12);
+                }
+            }
+        }
+        assert!((gradient.value(&Vec3::ey()) - Vec3::new(0.5, 0.7, 1.0)).length() < 1e-12);
+    }
+}
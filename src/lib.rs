@@ -1,11 +1,23 @@
+pub mod aov;
 pub mod camera;
+pub mod checkpoint;
+pub mod denoise;
+pub mod distributed;
+pub mod distribution;
+pub mod environment;
+pub mod film;
+pub mod filter;
 pub mod geometry;
+pub mod integrator;
+pub mod light;
 pub mod mat4;
 pub mod material;
 pub mod optimize;
 pub mod ray;
 pub mod render;
+pub mod sampler;
 pub mod sprite;
+pub mod tonemap;
 pub mod util;
 pub mod vec3;
 pub mod vec4;
@@ -0,0 +1,386 @@
+use crate::geometry::Rectangle;
+use crate::geometry::Sphere;
+use crate::geometry::TransformedGeometry;
+use crate::mat4::Mat4Cached;
+use crate::material::Material;
+use crate::ray::Hit;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::sprite::Sprite;
+use crate::util::randomUnitVector;
+use crate::vec3::Vec3;
+
+use std::f64::consts::PI;
+
+// 能在表面上随机取点的几何体，用来直接采样面光源
+// pdf都是相对于reference点的立体角来说的
+pub trait Sample: Hit {
+    // 返回采到的点、这个点的法向量、pdf。time是光线的时间，动起来的灯在不同时间位置不一样
+    fn sample(
+        &self,
+        reference: &Vec3,
+        time: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Vec3, Vec3, f64)>;
+
+    // 从reference往direction方向看，sample()采到这个方向的概率密度。看不到自己的话就是0
+    // 要求交，所以也要sampler
+    fn pdf(&self, reference: &Vec3, direction: &Vec3, time: f64, sampler: &mut dyn Sampler) -> f64;
+}
+
+// 面积pdf换成立体角pdf：乘距离平方、除以光源表面和连线夹角的cos
+fn areaToSolidAngle(areaPdf: f64, reference: &Vec3, point: &Vec3, normal: &Vec3) -> f64 {
+    let direction = *point - *reference;
+    let distanceSquared = direction.dot(&direction);
+    let cosine = normal.normalized().dot(&direction).abs() / distanceSquared.sqrt();
+    if cosine <= 0.0 || !cosine.is_finite() {
+        return 0.0;
+    }
+    return areaPdf * distanceSquared / cosine;
+}
+
+impl Sample for Rectangle {
+    fn sample(
+        &self,
+        reference: &Vec3,
+        time: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Vec3, Vec3, f64)> {
+        let (u, v) = sampler.next2D();
+        let point = Vec3::new((u - 0.5) * self.width(), (v - 0.5) * self.height(), 0.0);
+        let normal = Vec3::new(0.0, 0.0, 1.0);
+        let pdf = areaToSolidAngle(
+            1.0 / (self.width() * self.height()),
+            reference,
+            &point,
+            &normal,
+        );
+
+        if pdf > 0.0 {
+            return Some((point, normal, pdf));
+        } else {
+            return None;
+        }
+    }
+
+    fn pdf(&self, reference: &Vec3, direction: &Vec3, time: f64, sampler: &mut dyn Sampler) -> f64 {
+        if let Some(record) = self.hit(&Ray::new(*reference, *direction).withTime(time), sampler) {
+            return areaToSolidAngle(
+                1.0 / (self.width() * self.height()),
+                reference,
+                record.intersection(),
+                record.normal(),
+            );
+        } else {
+            return 0.0;
+        }
+    }
+}
+
+// 以w为z轴随便找两个和它垂直的单位向量
+fn orthonormalBasis(w: &Vec3) -> (Vec3, Vec3) {
+    let a = if w.x().abs() > 0.9 {
+        Vec3::ey()
+    } else {
+        Vec3::ex()
+    };
+    let u = w.cross(&a).normalized();
+    let v = w.cross(&u);
+    return (u, v);
+}
+
+// 在球外面的话，只采样从reference看过去能看到的那个圆锥，圆锥里每个方向的概率一样
+// 在球里面的话就在整个球面上均匀取点
+impl Sample for Sphere {
+    fn sample(
+        &self,
+        reference: &Vec3,
+        time: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Vec3, Vec3, f64)> {
+        let distanceSquared = reference.dot(reference);
+        let radiusSquared = self.radius() * self.radius();
+
+        if distanceSquared <= radiusSquared {
+            let point = randomUnitVector(sampler) * self.radius();
+            let normal = point / self.radius();
+            let pdf =
+                areaToSolidAngle(1.0 / (4.0 * PI * radiusSquared), reference, &point, &normal);
+            if pdf > 0.0 {
+                return Some((point, normal, pdf));
+            } else {
+                return None;
+            }
+        }
+
+        let cosMax = (1.0 - radiusSquared / distanceSquared).max(0.0).sqrt();
+        let oneMinusCosMax = radiusSquared / distanceSquared / (1.0 + cosMax); // 直接1 - cosMax的话，球很远的时候精度不够
+        let (u, v) = sampler.next2D();
+        let cosTheta = 1.0 - u * oneMinusCosMax;
+        let sinTheta = (1.0 - cosTheta * cosTheta).max(0.0).sqrt();
+        let phi = 2.0 * PI * v;
+
+        let w = (-*reference).normalized(); // 指向球心
+        let (u, v) = orthonormalBasis(&w);
+        let direction = u * (sinTheta * phi.cos()) + v * (sinTheta * phi.sin()) + w * cosTheta;
+
+        // 圆锥边缘上的方向可能因为精度问题擦着球过去了
+        let record = self.hit(&Ray::new(*reference, direction).withTime(time), sampler)?;
+        return Some((
+            *record.intersection(),
+            *record.normal(),
+            1.0 / (2.0 * PI * oneMinusCosMax),
+        ));
+    }
+
+    fn pdf(&self, reference: &Vec3, direction: &Vec3, time: f64, sampler: &mut dyn Sampler) -> f64 {
+        if let Some(record) = self.hit(&Ray::new(*reference, *direction).withTime(time), sampler) {
+            let distanceSquared = reference.dot(reference);
+            let radiusSquared = self.radius() * self.radius();
+
+            if distanceSquared <= radiusSquared {
+                return areaToSolidAngle(
+                    1.0 / (4.0 * PI * radiusSquared),
+                    reference,
+                    record.intersection(),
+                    record.normal(),
+                );
+            }
+
+            let cosMax = (1.0 - radiusSquared / distanceSquared).max(0.0).sqrt();
+            let oneMinusCosMax = radiusSquared / distanceSquared / (1.0 + cosMax);
+            return 1.0 / (2.0 * PI * oneMinusCosMax);
+        } else {
+            return 0.0;
+        }
+    }
+}
+
+// 变换前后同一块面积会被拉伸，所以要在面积pdf这一层换算
+// 局部坐标里的立体角pdf -> 局部面积pdf -> 世界面积pdf -> 世界立体角pdf
+// 面积的伸缩比例是|det(M)| * |M^{-T} n|，法向量也要用M^{-T}来变换，这样不等比缩放的时候也是对的
+fn transformedPdf(
+    transform: &Mat4Cached,
+    localReference: &Vec3,
+    localPoint: &Vec3,
+    localNormal: &Vec3,
+    localPdf: f64,
+    reference: &Vec3,
+) -> Option<(Vec3, Vec3, f64)> {
+    let inversed = transform.inversed()?;
+
+    let localDirection = *localPoint - *localReference;
+    let localDistanceSquared = localDirection.dot(&localDirection);
+    let localNormal = localNormal.normalized();
+    let localCosine = localNormal.dot(&localDirection).abs() / localDistanceSquared.sqrt();
+    let areaPdf = localPdf * localCosine / localDistanceSquared;
+
+    let normal: Vec3 = localNormal
+        .xyz0()
+        .transformed(&inversed.transposed())
+        .into();
+    let areaScale = transform.determinant().abs() * normal.length();
+    let point: Vec3 = localPoint.xyz1().transformed(transform.as_ref()).into();
+    let normal = normal.normalized();
+
+    let pdf = areaToSolidAngle(areaPdf / areaScale, reference, &point, &normal);
+    if pdf > 0.0 {
+        return Some((point, normal, pdf));
+    } else {
+        return None;
+    }
+}
+
+fn transformedSample<T>(
+    geometry: &T,
+    transform: &Mat4Cached,
+    reference: &Vec3,
+    time: f64,
+    sampler: &mut dyn Sampler,
+) -> Option<(Vec3, Vec3, f64)>
+where
+    T: Sample + ?Sized,
+{
+    let inversed = transform.inversed()?;
+    let localReference: Vec3 = reference.xyz1().transformed(inversed).into();
+    let (localPoint, localNormal, localPdf) = geometry.sample(&localReference, time, sampler)?;
+
+    return transformedPdf(
+        transform,
+        &localReference,
+        &localPoint,
+        &localNormal,
+        localPdf,
+        reference,
+    );
+}
+
+fn transformedDirectionPdf<T>(
+    geometry: &T,
+    transform: &Mat4Cached,
+    reference: &Vec3,
+    direction: &Vec3,
+    time: f64,
+    sampler: &mut dyn Sampler,
+) -> f64
+where
+    T: Sample + ?Sized,
+{
+    if let Some(inversed) = transform.inversed() {
+        let localReference: Vec3 = reference.xyz1().transformed(inversed).into();
+        let localDirection: Vec3 = direction.xyz0().transformed(inversed).into();
+
+        if let Some(record) = geometry.hit(
+            &Ray::new(localReference, localDirection).withTime(time),
+            sampler,
+        ) {
+            let localPdf = geometry.pdf(&localReference, &localDirection, time, sampler);
+            if localPdf <= 0.0 {
+                return 0.0;
+            }
+
+            if let Some((_, _, pdf)) = transformedPdf(
+                transform,
+                &localReference,
+                record.intersection(),
+                record.normal(),
+                localPdf,
+                reference,
+            ) {
+                return pdf;
+            }
+        }
+    }
+
+    return 0.0;
+}
+
+impl<T> Sample for TransformedGeometry<T>
+where
+    T: Sample,
+{
+    fn sample(
+        &self,
+        reference: &Vec3,
+        time: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Vec3, Vec3, f64)> {
+        return transformedSample(
+            self.geometry(),
+            &self.transformAt(time),
+            reference,
+            time,
+            sampler,
+        );
+    }
+
+    fn pdf(&self, reference: &Vec3, direction: &Vec3, time: f64, sampler: &mut dyn Sampler) -> f64 {
+        return transformedDirectionPdf(
+            self.geometry(),
+            &self.transformAt(time),
+            reference,
+            direction,
+            time,
+            sampler,
+        );
+    }
+}
+
+// Sprite和TransformedGeometry的变换是一样的，可惜又是两份impl
+impl<T, U> Sample for Sprite<T, U>
+where
+    T: Sample,
+    U: Material + 'static,
+{
+    fn sample(
+        &self,
+        reference: &Vec3,
+        time: f64,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Vec3, Vec3, f64)> {
+        if let Some(geometry) = self.geometry() {
+            return transformedSample(
+                geometry.as_ref(),
+                &self.transformAt(time),
+                reference,
+                time,
+                sampler,
+            );
+        } else {
+            return None;
+        }
+    }
+
+    fn pdf(&self, reference: &Vec3, direction: &Vec3, time: f64, sampler: &mut dyn Sampler) -> f64 {
+        if let Some(geometry) = self.geometry() {
+            return transformedDirectionPdf(
+                geometry.as_ref(),
+                &self.transformAt(time),
+                reference,
+                direction,
+                time,
+                sampler,
+            );
+        } else {
+            return 0.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::Rectangle;
+    use crate::geometry::Sphere;
+    use crate::geometry::TransformedGeometry;
+    use crate::light::Sample;
+    use crate::mat4::Mat4;
+    use crate::sampler::RandomSampler;
+    use crate::util::randomUnitVector;
+    use crate::vec3::Vec3;
+
+    use std::f64::consts::PI;
+
+    // 变换里带了不等比缩放。对所有方向积分pdf应该是1，sample()给的pdf也要和pdf()对得上
+    #[test]
+    fn transformedPdfIsConsistent() {
+        let transform = Mat4::translation(Vec3::new(1.0, 2.0, 3.0))
+            .multiplied(&Mat4::rotation(0.7, Vec3::new(1.0, 1.0, 0.0).normalized()))
+            .multiplied(&Mat4::scaling(Vec3::new(2.0, 0.5, 1.0)));
+        let lights: Vec<Box<dyn Sample>> = vec![
+            Box::new(TransformedGeometry::new(Sphere::new(1.0), transform)),
+            Box::new(TransformedGeometry::new(
+                Rectangle::new(2.0, 1.0),
+                transform,
+            )),
+        ];
+        // 离得太远的话几乎没有方向能看到它，积分的方差太大
+        let reference: Vec3 = Vec3::new(0.2, 0.1, 1.3)
+            .xyz1()
+            .transformed(&transform)
+            .into();
+
+        let mut sampler = RandomSampler::new(0);
+
+        for light in lights.iter() {
+            let n = 200000;
+            let mut sum = 0.0;
+            for _ in 0..n {
+                let direction = randomUnitVector(&mut sampler);
+                sum += light.pdf(&reference, &direction, 0.0, &mut sampler);
+            }
+            let integral = sum / n as f64 * 4.0 * PI;
+            assert!((integral - 1.0).abs() < 0.05, "{}", integral);
+
+            for _ in 0..100 {
+                let (point, _, pdf) = light.sample(&reference, 0.0, &mut sampler).unwrap();
+                let expected = light.pdf(&reference, &(point - reference), 0.0, &mut sampler);
+                assert!(
+                    (pdf - expected).abs() < 1e-6 * expected,
+                    "{} {}",
+                    pdf,
+                    expected
+                );
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::vec3::Vec3;
 
+use std::io::{Error, ErrorKind, Result};
 use std::ops::*;
 
 // 突然发现没办法抄gl-matrix的代码，因为gl-matrix的mat4是column-major的……
@@ -46,6 +47,16 @@ impl Mat4 {
         return Self { a: a };
     }
 
+    // 构造缩放变换矩阵
+    pub fn scaling(factor: Vec3) -> Self {
+        let mut a = [0.0; 16];
+        a[0] = factor[0];
+        a[5] = factor[1];
+        a[10] = factor[2];
+        a[15] = 1.0;
+        return Self { a: a };
+    }
+
     // 构造旋转变换矩阵
     // <http://glmatrix.net/docs/mat4.js.html#line937>
     // <https://en.wikipedia.org/wiki/Rotation_matrix#Axis_and_angle>
@@ -242,6 +253,19 @@ impl Mat4 {
         return Some(Self { a: a });
     }
 
+    // 转置，变换法向量要用逆矩阵的转置
+    pub fn transposed(&self) -> Self {
+        let mut a = [0.0; 16];
+
+        for i in 0..4 {
+            for j in 0..4 {
+                a[i * 4 + j] = self.a[j * 4 + i];
+            }
+        }
+
+        return Self { a: a };
+    }
+
     // 不知道这个合不合规范
     pub fn as_slice(&self) -> &[f64] {
         return &self.a;
@@ -405,6 +429,48 @@ mod tests {
         println!("{:#?}", mat * 9.0);
         println!("{:#?}", mat * 9.0);
     }
+
+    // 关键帧中间线性插值，两头停在原地，关键帧的顺序无所谓
+    #[test]
+    fn animatedTransformInterpolates() {
+        use crate::mat4::AnimatedTransform;
+        use crate::mat4::Mat4;
+        use crate::vec3::Vec3;
+
+        let motion = AnimatedTransform::new(vec![
+            (1.0, Mat4::translation(Vec3::new(2.0, 0.0, 0.0))),
+            (0.0, Mat4::translation(Vec3::new(0.0, 0.0, 0.0))),
+            (2.0, Mat4::translation(Vec3::new(2.0, 4.0, 0.0))),
+        ])
+        .unwrap();
+        let position = |time: f64| -> Vec3 {
+            return Vec3::new(0.0, 0.0, 0.0)
+                .xyz1()
+                .transformed(motion.at(time).origin())
+                .xyz();
+        };
+
+        assert!((position(-1.0) - Vec3::new(0.0, 0.0, 0.0)).length() < 1e-9);
+        assert!((position(0.25) - Vec3::new(0.5, 0.0, 0.0)).length() < 1e-9);
+        assert!((position(1.5) - Vec3::new(2.0, 2.0, 0.0)).length() < 1e-9);
+        assert!((position(3.0) - Vec3::new(2.0, 4.0, 0.0)).length() < 1e-9);
+        assert!(motion.at(0.5).inversed().is_some());
+    }
+
+    #[test]
+    fn animatedTransformRejectsBadKeyframes() {
+        use crate::mat4::AnimatedTransform;
+        use crate::mat4::Mat4;
+
+        use std::io::ErrorKind;
+
+        let error = AnimatedTransform::new(vec![]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        let error =
+            AnimatedTransform::new(vec![(0.0, Mat4::identity()), (f64::NAN, Mat4::identity())])
+                .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
 }
 
 // 加个cache吧，不然每次都要算逆矩阵真的太慢了……
@@ -467,3 +533,50 @@ impl AsRef<Mat4> for Mat4Cached {
         self.origin()
     }
 }
+
+// 动起来的物体用的变换：几个(时间, 矩阵)关键帧，中间按时间对矩阵的每个元素线性插值
+// 这样物体上每个点都是在两个关键帧的位置之间匀速直线运动，所以所有关键帧的bounding box合起来正好包住整个运动过程
+// 坏处是旋转的时候中间会缩一下，转得多的话多加几个关键帧
+// 第一个关键帧之前、最后一个关键帧之后都停在原地
+// 没有关键帧或者时间是NaN的话排不了序，直接报错
+#[derive(Clone, Debug)]
+pub struct AnimatedTransform {
+    keyframes: Vec<(f64, Mat4Cached)>, // 按时间排好序的
+}
+
+impl AnimatedTransform {
+    pub fn new(mut keyframes: Vec<(f64, Mat4)>) -> Result<Self> {
+        if keyframes.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "animated transform needs a keyframe",
+            ));
+        }
+        if keyframes.iter().any(|(t, _)| t.is_nan()) {
+            return Err(Error::new(ErrorKind::InvalidInput, "keyframe time is NaN"));
+        }
+        keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        return Ok(Self {
+            keyframes: keyframes.into_iter().map(|(t, m)| (t, m.into())).collect(),
+        });
+    }
+
+    pub fn keyframes(&self) -> &Vec<(f64, Mat4Cached)> {
+        return &self.keyframes;
+    }
+
+    pub fn at(&self, time: f64) -> Mat4Cached {
+        let next = self.keyframes.partition_point(|(t, _)| *t <= time);
+        if next == 0 {
+            return self.keyframes[0].1;
+        }
+        if next == self.keyframes.len() {
+            return self.keyframes[next - 1].1;
+        }
+
+        let (t0, m0) = &self.keyframes[next - 1];
+        let (t1, m1) = &self.keyframes[next];
+        let s = (time - t0) / (t1 - t0);
+        return (*m0.origin() * (1.0 - s) + *m1.origin() * s).into();
+    }
+}
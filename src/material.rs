@@ -1,23 +1,48 @@
 use crate::ray::HitRecord;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::util::randomInUnitSphere;
+use crate::util::randomUnitVector;
 use crate::vec3::Vec3;
 
-use rand::thread_rng;
-use rand::Rng;
-
 use std::f64::consts::PI;
 use std::fmt::Debug;
 use std::sync::Arc;
 
 pub trait Material: Send + Sync + Debug {
-    fn scatter(&self, rayIn: &Ray, hitRecord: &HitRecord) -> Option<(Ray, Vec3)>;
+    fn scatter(
+        &self,
+        rayIn: &Ray,
+        hitRecord: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Vec3)>;
     // 我觉得这里有点问题，真实世界里一束入射光会散射出多束反射光，但是这里只会返回一束光，以后怎么扩展成多束光呢
 
     fn emitted(&self, uv: &(f64, f64), point: &Vec3) -> Vec3 {
         // 这里纠结了一下要不要搞成Option<Vec3>
         return Vec3::new(0.0, 0.0, 0.0); // 默认不发光
     }
+
+    // 直接采样光源的时候，出射方向不是scatter()给的，需要知道任意方向上的BSDF值和scatter()采到这个方向的概率密度
+    // bsdf()返回的已经乘上了cos项，所以scatter()返回的attenuation应该正好等于bsdf() / pdf()
+    fn bsdf(&self, rayIn: &Ray, hitRecord: &HitRecord, direction: &Vec3) -> Vec3 {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
+
+    fn pdf(&self, rayIn: &Ray, hitRecord: &HitRecord, direction: &Vec3) -> f64 {
+        return 0.0;
+    }
+
+    // 只能靠scatter()采样的材质（比如镜面）就没法直接采样光源了
+    // 默认是true，这样没有实现bsdf()和pdf()的材质行为和以前一样
+    fn isSpecular(&self) -> bool {
+        return true;
+    }
+
+    // 表面本身的颜色，不管光照，输出albedo的AOV和降噪的时候用。默认是黑色
+    fn reflectance(&self, uv: &(f64, f64), point: &Vec3) -> Vec3 {
+        return Vec3::new(0.0, 0.0, 0.0);
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -58,15 +83,44 @@ impl Into<SolidColor> for Vec3 {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, rayIn: &Ray, hitRecord: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(
+        &self,
+        rayIn: &Ray,
+        hitRecord: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Vec3)> {
         // let target = *hitRecord.intersection() + *hitRecord.normal() + randomInUnitSphere(); // 会不会出现0向量啊
+        // 原来加的是randomInUnitSphere()，方向不是严格按cos分布的，算不出pdf，所以换成了球面上的点
+        let mut direction = *hitRecord.normal() + randomUnitVector(sampler);
+        if direction.length() < 1e-6 {
+            direction = *hitRecord.normal(); // 会出现0向量的
+        }
         let scattered = Ray::new(
             *hitRecord.intersection(),
-            (*hitRecord.normal() + randomInUnitSphere()).normalized(), // normalize一下吧……
-        );
+            direction.normalized(), // normalize一下吧……
+        )
+        .withTime(rayIn.time());
         let attenuation = self.albedo.value(hitRecord.uv(), hitRecord.intersection());
         return Some((scattered, attenuation));
     }
+
+    fn bsdf(&self, rayIn: &Ray, hitRecord: &HitRecord, direction: &Vec3) -> Vec3 {
+        let albedo = self.albedo.value(hitRecord.uv(), hitRecord.intersection());
+        return albedo * self.pdf(rayIn, hitRecord, direction);
+    }
+
+    fn pdf(&self, rayIn: &Ray, hitRecord: &HitRecord, direction: &Vec3) -> f64 {
+        let cosine = hitRecord.normal().normalized().dot(&direction.normalized());
+        return cosine.max(0.0) / PI;
+    }
+
+    fn isSpecular(&self) -> bool {
+        return false;
+    }
+
+    fn reflectance(&self, uv: &(f64, f64), point: &Vec3) -> Vec3 {
+        return self.albedo.value(uv, point);
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -96,7 +150,12 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, rayIn: &Ray, hitRecord: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(
+        &self,
+        rayIn: &Ray,
+        hitRecord: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Vec3)> {
         if rayIn.direction().dot(hitRecord.normal()) < 0.0 {
             let reflected = rayIn.direction().normalized().reflected(hitRecord.normal());
             let scattered = Ray::new(
@@ -104,9 +163,10 @@ impl Material for Metal {
                 if self.fuzziness == 0.0 {
                     reflected
                 } else {
-                    (reflected + self.fuzziness * randomInUnitSphere()).normalized()
+                    (reflected + self.fuzziness * randomInUnitSphere(sampler)).normalized()
                 },
-            );
+            )
+            .withTime(rayIn.time());
             let attenuation = self.albedo.value(hitRecord.uv(), hitRecord.intersection());
             return Some((scattered, attenuation));
         } else {
@@ -116,6 +176,44 @@ impl Material for Metal {
             return None;
         }
     }
+
+    // scatter()的attenuation永远是albedo，所以bsdf就是albedo * pdf
+    fn bsdf(&self, rayIn: &Ray, hitRecord: &HitRecord, direction: &Vec3) -> Vec3 {
+        let albedo = self.albedo.value(hitRecord.uv(), hitRecord.intersection());
+        return albedo * self.pdf(rayIn, hitRecord, direction);
+    }
+
+    // 模糊的方向是reflected + fuzziness * (单位球内均匀的一点)，也就是以reflected的终点为球心、半径fuzziness的球里均匀取一点
+    // 那么某个方向的概率密度就是这个方向的射线穿过这个小球那一段上 t^2 dt 的积分，再除以小球的体积
+    fn pdf(&self, rayIn: &Ray, hitRecord: &HitRecord, direction: &Vec3) -> f64 {
+        if self.fuzziness == 0.0 || rayIn.direction().dot(hitRecord.normal()) >= 0.0 {
+            return 0.0; // 完美镜面是delta分布，没有pdf
+        }
+
+        let reflected = rayIn.direction().normalized().reflected(hitRecord.normal());
+        let c = direction.normalized().dot(&reflected);
+        let discriminant = c * c - reflected.dot(&reflected) + self.fuzziness * self.fuzziness;
+        if discriminant <= 0.0 {
+            return 0.0;
+        }
+
+        let t0 = (c - discriminant.sqrt()).max(0.0);
+        let t1 = c + discriminant.sqrt();
+        if t1 <= 0.0 {
+            return 0.0;
+        }
+
+        let volume = 4.0 / 3.0 * PI * self.fuzziness.powi(3);
+        return (t1.powi(3) - t0.powi(3)) / 3.0 / volume;
+    }
+
+    fn isSpecular(&self) -> bool {
+        return self.fuzziness == 0.0;
+    }
+
+    fn reflectance(&self, uv: &(f64, f64), point: &Vec3) -> Vec3 {
+        return self.albedo.value(uv, point);
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -144,7 +242,12 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, rayIn: &Ray, hitRecord: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(
+        &self,
+        rayIn: &Ray,
+        hitRecord: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Vec3)> {
         let attenuation = Vec3::new(1.0, 1.0, 1.0);
         let mut refractiveInOverOut = 1.0;
         let mut normal = *hitRecord.normal();
@@ -161,19 +264,20 @@ impl Material for Dielectric {
         if let Some(refracted) = rayIn.direction().refracted(&normal, refractiveInOverOut) {
             // 这边忘记考虑菲涅尔效应了 <https://en.wikipedia.org/wiki/Fresnel_equations> ，即使是外表面，也有几率反射而不是折射
             let theta = (-rayIn.direction().dot(&normal)).acos();
-            if thread_rng().gen_range(0.0, 1.0)
+            if sampler.next1D()
                 < Dielectric::schlickReflectionProbability(theta, refractiveInOverOut, 1.0)
             {
                 // 此时应该反射
                 let reflected = rayIn.direction().reflected(hitRecord.normal());
                 return Some((
-                    Ray::new(hitRecord.intersection().clone(), reflected),
+                    Ray::new(hitRecord.intersection().clone(), reflected).withTime(rayIn.time()),
                     attenuation,
                 ));
             } else {
                 // 此时应该折射
                 return Some((
-                    Ray::new(*hitRecord.intersection(), refracted.normalized()),
+                    Ray::new(*hitRecord.intersection(), refracted.normalized())
+                        .withTime(rayIn.time()),
                     attenuation,
                 ));
             }
@@ -183,13 +287,21 @@ impl Material for Dielectric {
                 Ray::new(
                     *hitRecord.intersection(),
                     rayIn.direction().reflected(&normal),
-                ),
+                )
+                .withTime(rayIn.time()),
                 attenuation,
             ));
             // 但是图上有明显的一圈一圈的杂质，不知道是什么原因
             // 破案了，是浮点数精度的那个问题。解决了浮点数精度问题就好了
         }
     }
+
+    // 反射和折射都是delta分布，只能靠scatter()采样，bsdf()、pdf()、isSpecular()用默认的就行
+
+    // 光线全部穿过去或者反射回来，不吸收，当成白色
+    fn reflectance(&self, uv: &(f64, f64), point: &Vec3) -> Vec3 {
+        return Vec3::new(1.0, 1.0, 1.0);
+    }
 }
 
 // 纹理，直接按uv和空间坐标返回颜色
@@ -288,7 +400,12 @@ impl DiffuseLight {
 }
 
 impl Material for DiffuseLight {
-    fn scatter(&self, rayIn: &Ray, hitRecord: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(
+        &self,
+        rayIn: &Ray,
+        hitRecord: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Vec3)> {
         return None;
     }
 
@@ -315,12 +432,75 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, rayIn: &Ray, hitRecord: &HitRecord) -> Option<(Ray, Vec3)> {
-        let scattered = Ray::new(
-            hitRecord.intersection().clone(),
-            randomInUnitSphere().normalized(),
-        );
+    fn scatter(
+        &self,
+        rayIn: &Ray,
+        hitRecord: &HitRecord,
+        sampler: &mut dyn Sampler,
+    ) -> Option<(Ray, Vec3)> {
+        let scattered = Ray::new(hitRecord.intersection().clone(), randomUnitVector(sampler))
+            .withTime(rayIn.time());
         let attenuation = self.albedo.value(hitRecord.uv(), hitRecord.intersection());
         return Some((scattered, attenuation));
     }
+
+    // 各个方向概率一样，整个球面的立体角是4pi。没有cos项
+    fn bsdf(&self, rayIn: &Ray, hitRecord: &HitRecord, direction: &Vec3) -> Vec3 {
+        let albedo = self.albedo.value(hitRecord.uv(), hitRecord.intersection());
+        return albedo * self.pdf(rayIn, hitRecord, direction);
+    }
+
+    fn pdf(&self, rayIn: &Ray, hitRecord: &HitRecord, direction: &Vec3) -> f64 {
+        return 1.0 / (4.0 * PI);
+    }
+
+    fn isSpecular(&self) -> bool {
+        return false;
+    }
+
+    fn reflectance(&self, uv: &(f64, f64), point: &Vec3) -> Vec3 {
+        return self.albedo.value(uv, point);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::material::Material;
+    use crate::material::Metal;
+    use crate::ray::HitRecord;
+    use crate::ray::Ray;
+    use crate::sampler::RandomSampler;
+    use crate::util::randomUnitVector;
+    use crate::vec3::Vec3;
+
+    use std::f64::consts::PI;
+
+    // 在整个球面上均匀撒点积分pdf，应该是1
+    #[test]
+    fn metalPdfIntegratesToOne() {
+        let rayIn = Ray::new(
+            Vec3::new(0.0, 1.0, -1.0),
+            Vec3::new(0.0, -1.0, 1.0).normalized(),
+        );
+        let hitRecord = HitRecord::new(
+            1.0,
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            None,
+            (0.0, 0.0),
+        );
+
+        let mut sampler = RandomSampler::new(0);
+
+        for fuzziness in [0.8, 1.5].iter() {
+            let metal = Metal::new(Vec3::new(1.0, 1.0, 1.0), *fuzziness);
+            let n = 200000;
+            let mut sum = 0.0;
+            for _ in 0..n {
+                sum += metal.pdf(&rayIn, &hitRecord, &randomUnitVector(&mut sampler));
+            }
+            let integral = sum / n as f64 * 4.0 * PI;
+            assert!((integral - 1.0).abs() < 0.05, "{} {}", fuzziness, integral);
+        }
+    }
 }
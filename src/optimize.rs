@@ -1,17 +1,19 @@
 use crate::geometry::Rectangle;
 use crate::geometry::Sphere;
 use crate::geometry::TransformedGeometry;
+use crate::mat4::AnimatedTransform;
+use crate::mat4::Mat4;
+use crate::mat4::Mat4Cached;
 use crate::material::Material;
 use crate::ray::Hit;
 use crate::ray::HitRecord;
 use crate::ray::Ray;
+use crate::sampler::RandomSampler;
+use crate::sampler::Sampler;
 use crate::sprite::Sprite;
 use crate::vec3::Vec3;
 use crate::volume::ConstantMedium;
 
-use rand::thread_rng;
-use rand::Rng;
-
 use std::cmp::Ordering;
 use std::fmt::Debug;
 use std::mem::swap;
@@ -55,10 +57,48 @@ impl AxisAlignedBoundingBox {
         );
         return AxisAlignedBoundingBox::new(min, max);
     }
+
+    // 把8个角都变换过去，再找一个包住它们的AABB
+    pub fn transformed(&self, transform: &Mat4) -> Self {
+        let (x0, y0, z0) = (self.min[0], self.min[1], self.min[2]);
+        let (x1, y1, z1) = (self.max[0], self.max[1], self.max[2]);
+
+        let mut min = [f64::INFINITY; 3];
+        let mut max = [f64::NEG_INFINITY; 3];
+
+        for point in [
+            Vec3::new(x0, y0, z0),
+            Vec3::new(x1, y0, z0),
+            Vec3::new(x0, y1, z0),
+            Vec3::new(x0, y0, z1),
+            Vec3::new(x1, y1, z0),
+            Vec3::new(x1, y0, z1),
+            Vec3::new(x0, y1, z1),
+            Vec3::new(x1, y1, z1),
+        ]
+        .iter()
+        .map(|v| v.xyz1().transformed(transform).xyz())
+        {
+            for i in 0..3 {
+                if point[i] < min[i] {
+                    min[i] = point[i];
+                }
+
+                if point[i] > max[i] {
+                    max[i] = point[i];
+                }
+            }
+        }
+
+        return AxisAlignedBoundingBox::new(
+            Vec3::new(min[0], min[1], min[2]),
+            Vec3::new(max[0], max[1], max[2]),
+        );
+    }
 }
 
 impl Hit for AxisAlignedBoundingBox {
-    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<HitRecord> {
         // 这种实现我觉得并不是很直观……但是好像可以避免nan的问题
         let mut tmin = 0.0;
         let mut tmax = 1.0 / 0.0; // inf
@@ -131,56 +171,8 @@ where
     U: Material + 'static, // 可是这里根本和material没关系啊
 {
     fn bound(&self) -> Option<AxisAlignedBoundingBox> {
-        if let Some(geometry) = self.geometry() {
-            let transform = self.transform();
-
-            if let Some(bound) = geometry.bound() {
-                let x0 = bound.min()[0];
-                let y0 = bound.min()[1];
-                let z0 = bound.min()[2];
-
-                let x1 = bound.max()[0];
-                let y1 = bound.max()[1];
-                let z1 = bound.max()[2];
-
-                let mut min = vec![1.0 / 0.0; 3];
-                let mut max = vec![-1.0 / 0.0; 3];
-
-                for point in [
-                    Vec3::new(x0, y0, z0),
-                    Vec3::new(x1, y0, z0),
-                    Vec3::new(x0, y1, z0),
-                    Vec3::new(x0, y0, z1),
-                    Vec3::new(x1, y1, z0),
-                    Vec3::new(x1, y0, z1),
-                    Vec3::new(x0, y1, z1),
-                    Vec3::new(x1, y1, z1),
-                ]
-                .iter()
-                .map(|v| v.xyz1().transformed(transform.as_ref()).xyz())
-                {
-                    for i in 0..3 {
-                        if point[i] < min[i] {
-                            min[i] = point[i];
-                        }
-
-                        if point[i] > max[i] {
-                            max[i] = point[i];
-                        }
-                    }
-                }
-
-                let bound = AxisAlignedBoundingBox::new(
-                    Vec3::new(min[0], min[1], min[2]),
-                    Vec3::new(max[0], max[1], max[2]),
-                );
-                return Some(bound);
-            } else {
-                return None;
-            }
-        } else {
-            return None;
-        }
+        let bound = self.geometry().as_ref()?.bound()?;
+        return Some(transformedBound(&bound, self.transform(), self.motion()));
     }
 }
 
@@ -190,53 +182,27 @@ where
     T: Bound<AxisAlignedBoundingBox>,
 {
     fn bound(&self) -> Option<AxisAlignedBoundingBox> {
-        let geometry = self.geometry();
-        let transform = self.transform();
-
-        if let Some(bound) = geometry.bound() {
-            let x0 = bound.min()[0];
-            let y0 = bound.min()[1];
-            let z0 = bound.min()[2];
-
-            let x1 = bound.max()[0];
-            let y1 = bound.max()[1];
-            let z1 = bound.max()[2];
-
-            let mut min = vec![1.0 / 0.0; 3];
-            let mut max = vec![-1.0 / 0.0; 3];
-
-            for point in [
-                Vec3::new(x0, y0, z0),
-                Vec3::new(x1, y0, z0),
-                Vec3::new(x0, y1, z0),
-                Vec3::new(x0, y0, z1),
-                Vec3::new(x1, y1, z0),
-                Vec3::new(x1, y0, z1),
-                Vec3::new(x0, y1, z1),
-                Vec3::new(x1, y1, z1),
-            ]
-            .iter()
-            .map(|v| v.xyz1().transformed(transform.as_ref()).xyz())
-            {
-                for i in 0..3 {
-                    if point[i] < min[i] {
-                        min[i] = point[i];
-                    }
+        let bound = self.geometry().bound()?;
+        return Some(transformedBound(&bound, self.transform(), self.motion()));
+    }
+}
 
-                    if point[i] > max[i] {
-                        max[i] = point[i];
-                    }
-                }
+// 动起来的话把每个关键帧的bounding box合起来，插值的时候每个点都在关键帧之间走直线，所以一定包得住
+fn transformedBound(
+    bound: &AxisAlignedBoundingBox,
+    transform: &Mat4Cached,
+    motion: &Option<AnimatedTransform>,
+) -> AxisAlignedBoundingBox {
+    match motion {
+        Some(motion) => {
+            let keyframes = motion.keyframes();
+            let mut res = bound.transformed(keyframes[0].1.origin());
+            for (_, transform) in keyframes.iter().skip(1) {
+                res = res.merged(&bound.transformed(transform.origin()));
             }
-
-            let bound = AxisAlignedBoundingBox::new(
-                Vec3::new(min[0], min[1], min[2]),
-                Vec3::new(max[0], max[1], max[2]),
-            );
-            return Some(bound);
-        } else {
-            return None;
+            return res;
         }
+        None => return bound.transformed(transform.origin()),
     }
 }
 
@@ -293,11 +259,11 @@ impl Bound<AxisAlignedBoundingBox> for Vec<Arc<dyn Bound<AxisAlignedBoundingBox>
 
 // 这里怎么又要写一遍……明明Vec<Box<dyn Hit>>一定满足Hit、Bound<AABB>又是Hit的，说明Vec<Box<dyn Bound<AABB>>>肯定是Vec<Box<dyn Hit>>的子集，为啥还要写一遍呢……
 impl Hit for Vec<Box<dyn Bound<AxisAlignedBoundingBox>>> {
-    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<HitRecord> {
         let mut res = None;
 
         for v in self.iter() {
-            if let Some(record) = v.hit(ray) {
+            if let Some(record) = v.hit(ray, sampler) {
                 if res.is_none() {
                     res.replace(record);
                 } else {
@@ -314,11 +280,11 @@ impl Hit for Vec<Box<dyn Bound<AxisAlignedBoundingBox>>> {
 
 // 还要给Arc写一遍……
 impl Hit for Vec<Arc<dyn Bound<AxisAlignedBoundingBox>>> {
-    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<HitRecord> {
         let mut res = None;
 
         for v in self.iter() {
-            if let Some(record) = v.hit(ray) {
+            if let Some(record) = v.hit(ray, sampler) {
                 if res.is_none() {
                     res.replace(record);
                 } else {
@@ -363,7 +329,15 @@ where
 impl BoundingVolumeHierarchyNode<AxisAlignedBoundingBox> {
     // 这里没法传入&[Arc<dyn Bound<_>>]，因为下面要sort，只能move进来了
     // 那能不能传入Vec<&dyn Bound<_>>呢，那可能要标记lifetime了
+    // 每次按哪个轴切分还是随机的，但是种子固定，同样的objects建出来的树每次都一样
     pub fn new(objects: Vec<Arc<dyn Bound<AxisAlignedBoundingBox>>>) -> Option<Self> {
+        return Self::withSampler(objects, &mut RandomSampler::new(0));
+    }
+
+    pub fn withSampler(
+        objects: Vec<Arc<dyn Bound<AxisAlignedBoundingBox>>>,
+        sampler: &mut dyn Sampler,
+    ) -> Option<Self> {
         if objects.is_empty() {
             // 空的怎么办……
             return None;
@@ -371,8 +345,7 @@ impl BoundingVolumeHierarchyNode<AxisAlignedBoundingBox> {
 
         let mut objects = objects;
 
-        let mut generator = thread_rng();
-        let axis = generator.gen_range(0, 3);
+        let axis = ((sampler.next1D() * 3.0) as usize).min(2);
         let compare = match axis {
             0 => Self::compareX,
             1 => Self::compareY,
@@ -401,11 +374,11 @@ impl BoundingVolumeHierarchyNode<AxisAlignedBoundingBox> {
                 }
             }); // 为什么&Arc<dyn ...>不会自动cast到&dyn ...？
             let middle = objects.len() / 2;
-            right = Self::new(objects.split_off(middle))
+            right = Self::withSampler(objects.split_off(middle), sampler)
                 .map(|v| Arc::new(v) as Arc<dyn Bound<AxisAlignedBoundingBox>>);
             // split_off()会把vec分成两个vec，返回右半边，原来的被截断到左半边
-            left =
-                Self::new(objects).map(|v| Arc::new(v) as Arc<dyn Bound<AxisAlignedBoundingBox>>);
+            left = Self::withSampler(objects, sampler)
+                .map(|v| Arc::new(v) as Arc<dyn Bound<AxisAlignedBoundingBox>>);
             // 这好难看啊
         }
 
@@ -466,12 +439,12 @@ where
     T: Bound<T>,
 {
     // 递归的写法。什么时候试下BFS
-    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
-        if let Some(record) = self.volume.hit(ray) {
+    fn hit(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<HitRecord> {
+        if let Some(record) = self.volume.hit(ray, sampler) {
             let mut record = record;
 
             if let Some(left) = &self.left {
-                if let Some(leftRecord) = left.hit(ray) {
+                if let Some(leftRecord) = left.hit(ray, sampler) {
                     if leftRecord.t() < record.t() {
                         // bounding box的t是inf，所以放心大胆地比
                         record = leftRecord;
@@ -480,7 +453,7 @@ where
             }
 
             if let Some(right) = &self.right {
-                if let Some(rightRecord) = right.hit(ray) {
+                if let Some(rightRecord) = right.hit(ray, sampler) {
                     if rightRecord.t() < record.t() {
                         record = rightRecord;
                     }
@@ -514,3 +487,51 @@ where
         return self.boundary().bound();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::geometry::Sphere;
+    use crate::mat4::AnimatedTransform;
+    use crate::mat4::Mat4;
+    use crate::material::Lambertian;
+    use crate::optimize::AxisAlignedBoundingBox;
+    use crate::optimize::Bound;
+    use crate::optimize::BoundingVolumeHierarchyNode;
+    use crate::ray::Hit;
+    use crate::ray::Ray;
+    use crate::sampler::RandomSampler;
+    use crate::sprite::Sprite;
+    use crate::vec3::Vec3;
+
+    use std::sync::Arc;
+
+    // 球在快门时间里从x = 0走到x = 4，bounding box要包住整段路，BVH在中间的时间也要打得到
+    #[test]
+    fn movingSpriteBoundCoversMotion() {
+        let sphere = |keyframes: Vec<(f64, Mat4)>| -> Arc<dyn Bound<AxisAlignedBoundingBox>> {
+            return Arc::new(
+                Sprite::builder()
+                    .geometry(Sphere::new(0.5).into())
+                    .material(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)).into())
+                    .motion(AnimatedTransform::new(keyframes).unwrap())
+                    .build(),
+            );
+        };
+        let moving = sphere(vec![
+            (0.0, Mat4::translation(Vec3::new(0.0, 0.0, 0.0))),
+            (1.0, Mat4::translation(Vec3::new(4.0, 0.0, 0.0))),
+        ]);
+        let bound = moving.bound().unwrap();
+        assert!(bound.min().x() <= -0.5 && bound.max().x() >= 4.5);
+
+        let still = sphere(vec![(0.0, Mat4::translation(Vec3::new(0.0, 10.0, 0.0)))]);
+        let world = BoundingVolumeHierarchyNode::new(vec![moving, still]).unwrap();
+        let mut sampler = RandomSampler::new(0);
+        let ray = |time: f64| Ray::new(Vec3::new(2.0, 0.0, 5.0), -Vec3::ez()).withTime(time);
+
+        assert!(world.hit(&ray(0.0), &mut sampler).is_none());
+        let record = world.hit(&ray(0.5), &mut sampler).unwrap();
+        assert!((record.t() - 4.5).abs() < 1e-6);
+        assert!(world.hit(&ray(1.0), &mut sampler).is_none());
+    }
+}
@@ -1,4 +1,5 @@
 use crate::material::Material;
+use crate::sampler::Sampler;
 use crate::vec3::Vec3;
 
 use std::sync::Arc;
@@ -7,6 +8,7 @@ use std::sync::Arc;
 pub struct Ray {
     origin: Vec3,
     direction: Vec3, // 必须是单位向量
+    time: f64, // 快门打开以后什么时候发出的，动起来的物体按这个时间算位置。反射出去的光线和原来的是同一个时间
 }
 
 impl Ray {
@@ -14,9 +16,15 @@ impl Ray {
         Self {
             origin: origin,
             direction: direction,
+            time: 0.0,
         }
     }
 
+    pub fn withTime(mut self, time: f64) -> Self {
+        self.time = time;
+        return self;
+    }
+
     pub fn origin(&self) -> &Vec3 {
         // 这里如果返回Vec3会stackoverflow，也是活久见……
         // 后来发现并不是这个问题……是color()那里递归深度太大了
@@ -27,6 +35,10 @@ impl Ray {
         return &self.direction;
     }
 
+    pub fn time(&self) -> f64 {
+        return self.time;
+    }
+
     pub fn at(&self, t: f64) -> Vec3 {
         return *self.origin() + *self.direction() * t;
     }
@@ -40,6 +52,7 @@ pub struct HitRecord<'a> {
     // material: Option<Arc<dyn Material>>,
     material: Option<&'a dyn Material>, // 能不能有一天改成ref呢
     uv: (f64, f64),
+    objectId: u32, // 打到的是哪个Sprite，0表示没有编号
 }
 
 impl<'a> HitRecord<'a> {
@@ -57,9 +70,16 @@ impl<'a> HitRecord<'a> {
             normal: normal,
             material: material,
             uv: uv,
+            objectId: 0,
         }
     }
 
+    // 几何体不知道自己属于哪个Sprite，由Sprite在外面补上
+    pub fn withObjectId(mut self, objectId: u32) -> Self {
+        self.objectId = objectId;
+        return self;
+    }
+
     pub fn t(&self) -> f64 {
         return self.t;
     }
@@ -80,10 +100,15 @@ impl<'a> HitRecord<'a> {
     pub fn uv(&self) -> &(f64, f64) {
         return &self.uv;
     }
+
+    pub fn objectId(&self) -> u32 {
+        return self.objectId;
+    }
 }
 
+// 大部分几何体求交用不到随机数，只有烟雾这种要在光线飞行的路上随机选一点，所以也要把sampler传进来
 pub trait Hit: Send + Sync {
-    fn hit(&self, ray: &Ray) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<HitRecord>;
 }
 
 impl Default for HitRecord<'_> {
@@ -94,6 +119,7 @@ impl Default for HitRecord<'_> {
             normal: Vec3::new(0.0, 0.0, 0.0),
             material: None,
             uv: (0.0, 0.0),
+            objectId: 0,
         }
     }
 }
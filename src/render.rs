@@ -1,16 +1,53 @@
+use crate::aov::Aov;
+use crate::camera::Camera;
+use crate::environment::luminance;
+use crate::environment::ConstantEnvironment;
+use crate::environment::Environment;
+use crate::film::Film;
+use crate::filter::BoxFilter;
+use crate::filter::Filter;
+use crate::integrator::Integrator;
+use crate::integrator::PathTracer;
 use crate::ray::Hit;
+use crate::ray::HitRecord;
 use crate::ray::Ray;
+use crate::sampler::RandomSampler;
+use crate::sampler::Sampler;
 use crate::vec3::Vec3;
 
-pub fn color(ray: &Ray, world: &dyn Hit, maxDepth: usize) -> Vec3 {
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+use std::ops::Range;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+pub fn color(ray: &Ray, world: &dyn Hit, maxDepth: usize, sampler: &mut dyn Sampler) -> Vec3 {
+    // 背景设置成黑色更容易看出光照的效果
+    return colorInEnvironment(ray, world, &ConstantEnvironment::black(), maxDepth, sampler);
+}
+
+pub fn colorInEnvironment(
+    ray: &Ray,
+    world: &dyn Hit,
+    environment: &dyn Environment,
+    maxDepth: usize,
+    sampler: &mut dyn Sampler,
+) -> Vec3 {
     if maxDepth == 0 {
         return Vec3::new(0.0, 0.0, 0.0);
     }
 
-    if let Some(record) = world.hit(ray) {
+    if let Some(record) = world.hit(ray, sampler) {
         if let Some(material) = record.material() {
-            if let Some((scattered, attenuation)) = material.scatter(ray, &record) {
-                return attenuation * color(&scattered, world, maxDepth - 1)
+            if let Some((scattered, attenuation)) = material.scatter(ray, &record, sampler) {
+                return attenuation
+                    * colorInEnvironment(&scattered, world, environment, maxDepth - 1, sampler)
                     + material.emitted(record.uv(), record.intersection());
             } else {
                 return material.emitted(record.uv(), record.intersection());
@@ -20,10 +57,1301 @@ pub fn color(ray: &Ray, world: &dyn Hit, maxDepth: usize) -> Vec3 {
         }
     } else {
         // 背景
-        // let unitDirection = ray.direction().normalized();
-        // let t = 0.5 * (unitDirection.y() + 1.0);
-        // return Vec3::new(1.0, 1.0, 1.0) * (1.0 - t) + Vec3::new(0.5, 0.7, 1.0) * t;
-        return Vec3::new(0.0, 0.0, 0.0);
-        // 背景设置成黑色更容易看出光照的效果
+        return environment.value(ray.direction());
+    }
+}
+
+// 画面上的一小块矩形区域，线程每次领一块去渲染
+// 坐标和examples里一样，y = 0是最下面一行
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Tile {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Tile {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x: x,
+            y: y,
+            width: width,
+            height: height,
+        }
+    }
+
+    pub fn x(&self) -> usize {
+        return self.x;
+    }
+
+    pub fn y(&self) -> usize {
+        return self.y;
+    }
+
+    pub fn width(&self) -> usize {
+        return self.width;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.height;
+    }
+}
+
+// 渲染进度，每渲染完一个tile就交给Renderer的progress callback一次
+#[derive(Copy, Clone, Debug)]
+pub struct Progress {
+    tilesDone: usize,
+    tileCount: usize,   // 渐进式渲染的时候是每一遍的tile数乘以最多渲染几遍
+    sampleCount: usize, // 这次渲染一共采了多少个样本，不算从checkpoint读回来的
+    start: Instant,
+    elapsed: Duration,
+    timeBudget: Option<Duration>,
+}
+
+impl Progress {
+    pub(crate) fn new(tileCount: usize, timeBudget: Option<Duration>) -> Self {
+        Self {
+            tilesDone: 0,
+            tileCount: tileCount,
+            sampleCount: 0,
+            start: Instant::now(),
+            elapsed: Duration::ZERO,
+            timeBudget: timeBudget,
+        }
+    }
+
+    pub(crate) fn add(&mut self, sampleCount: usize) {
+        self.tilesDone += 1;
+        self.sampleCount += sampleCount;
+        self.elapsed = self.start.elapsed();
+    }
+
+    pub fn tilesDone(&self) -> usize {
+        return self.tilesDone;
+    }
+
+    pub fn tileCount(&self) -> usize {
+        return self.tileCount;
+    }
+
+    pub fn sampleCount(&self) -> usize {
+        return self.sampleCount;
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        return self.elapsed;
+    }
+
+    pub fn samplesPerSecond(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        return self.sampleCount as f64 / self.elapsed.as_secs_f64();
+    }
+
+    // 按到目前为止每个tile的平均时间估计还要多久，一个tile都还没渲染完的话是None
+    // 自适应采样和targetNoise可能让渲染提前结束，所以这是个上限。设了timeBudget的话不会超过剩下的时间
+    pub fn eta(&self) -> Option<Duration> {
+        if self.tilesDone == 0 {
+            return None;
+        }
+        let remaining = self.tileCount.saturating_sub(self.tilesDone) as u32;
+        let mut res = self.elapsed / self.tilesDone as u32 * remaining;
+        if let Some(timeBudget) = self.timeBudget {
+            res = res.min(timeBudget.saturating_sub(self.elapsed));
+        }
+        return Some(res);
+    }
+}
+
+// 用来从别的线程（比如GUI）取消渲染，复制出来的都是同一个
+// 正在渲染的tile会渲染完，只是不再开始新的tile
+// 取消以后一直是取消的状态，同一个Renderer要再渲染的话先reset()
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn isCancelled(&self) -> bool {
+        return self.cancelled.load(Ordering::SeqCst);
+    }
+
+    // 所有复制出来的token都会一起恢复。不要在渲染的时候调用，不然可能只取消了一半
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+    }
+}
+
+// 一个pixel到目前为止的所有样本，用来决定这个pixel还要不要继续采样
+// 方差是按亮度算的，用Welford的方法一边加样本一边更新，不用把样本都存下来
+// <https://en.wikipedia.org/wiki/Algorithms_for_calculating_variance#Welford's_online_algorithm>
+#[derive(Copy, Clone, Debug)]
+pub struct PixelStatistics {
+    pub(crate) sum: Vec3,
+    pub(crate) sampleCount: usize,
+    pub(crate) mean: f64,             // 亮度的平均值
+    pub(crate) m2: f64,               // 亮度和平均值之差的平方和
+    pub(crate) unblockedCount: usize, // 没被镜头挡住的样本数，AOV只看这些样本
+}
+
+impl PixelStatistics {
+    pub fn new() -> Self {
+        Self {
+            sum: Vec3::new(0.0, 0.0, 0.0),
+            sampleCount: 0,
+            mean: 0.0,
+            m2: 0.0,
+            unblockedCount: 0,
+        }
+    }
+
+    pub fn add(&mut self, sample: Vec3) {
+        self.push(sample);
+        self.unblockedCount += 1;
+    }
+
+    // 被镜头挡住的样本，颜色算黑的，但是不算进unblockedCount
+    pub fn addBlocked(&mut self) {
+        self.push(Vec3::new(0.0, 0.0, 0.0));
+    }
+
+    fn push(&mut self, sample: Vec3) {
+        self.sum += sample;
+        self.sampleCount += 1;
+
+        let value = luminance(&sample);
+        let delta = value - self.mean;
+        self.mean += delta / self.sampleCount as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn sampleCount(&self) -> usize {
+        return self.sampleCount;
+    }
+
+    pub fn unblockedCount(&self) -> usize {
+        return self.unblockedCount;
+    }
+
+    pub fn sum(&self) -> &Vec3 {
+        return &self.sum;
+    }
+
+    // 目前的估计值，一个样本都没有的话是黑色
+    pub fn color(&self) -> Vec3 {
+        if self.sampleCount == 0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+        return self.sum / self.sampleCount as f64;
+    }
+
+    // 亮度的样本方差
+    pub fn variance(&self) -> f64 {
+        if self.sampleCount < 2 {
+            return 0.0;
+        }
+        return self.m2 / (self.sampleCount - 1) as f64;
+    }
+
+    // 平均值的标准误差除以平均值。暗的地方同样的绝对误差更显眼，所以用相对误差
+    // 分母加一点点，不然全黑的pixel偶尔有一个亮的样本就除以0了
+    pub fn relativeError(&self) -> f64 {
+        if self.sampleCount < 2 {
+            return f64::INFINITY;
+        }
+        let standardError = (self.variance() / self.sampleCount as f64).sqrt();
+        return standardError / (self.mean.abs() + 1e-3);
+    }
+}
+
+impl Default for PixelStatistics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 整个画面到目前为止的结果
+pub(crate) struct Accumulation {
+    pub(crate) statistics: Vec<Vec<PixelStatistics>>, // 每个pixel自己的样本，用来决定要不要继续采样
+    pub(crate) sums: Vec<Vec<Vec3>>, // 所有样本按filter的权重splat到这个pixel上的颜色之和
+    pub(crate) weights: Vec<Vec<f64>>, // 权重之和
+    pub(crate) aovs: Vec<Vec<Vec<Vec3>>>, // 每种AOV一张，这个pixel自己没被挡住的样本的值之和，不splat
+}
+
+impl Accumulation {
+    pub(crate) fn new(width: usize, height: usize, aovCount: usize) -> Self {
+        Self {
+            statistics: vec![vec![PixelStatistics::new(); width]; height],
+            sums: vec![vec![Vec3::new(0.0, 0.0, 0.0); width]; height],
+            weights: vec![vec![0.0; width]; height],
+            aovs: vec![vec![vec![Vec3::new(0.0, 0.0, 0.0); width]; height]; aovCount],
+        }
+    }
+
+    // 加权平均。filter有负的瓣的话权重之和可能是0，就当成黑色
+    pub(crate) fn film(&self, aovs: &[Aov]) -> Film {
+        let height = self.sums.len();
+        let width = self.sums.first().map(|row| row.len()).unwrap_or(0);
+        let mut res = Film::new(width, height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let weight = self.weights[y][x];
+                if weight != 0.0 {
+                    res.setPixel(x, y, &(self.sums[y][x] / weight));
+                }
+            }
+        }
+
+        for (aov, sums) in aovs.iter().zip(self.aovs.iter()) {
+            let mut film = Film::new(width, height);
+            for (y, row) in sums.iter().enumerate() {
+                for (x, sum) in row.iter().enumerate() {
+                    let statistics = &self.statistics[y][x];
+                    if *aov == Aov::SampleCount {
+                        let count = statistics.sampleCount() as f64;
+                        film.setPixel(x, y, &Vec3::new(count, count, count));
+                    } else if aov.isAveraged() && statistics.unblockedCount() > 0 {
+                        film.setPixel(x, y, &(*sum / statistics.unblockedCount() as f64));
+                    } else {
+                        film.setPixel(x, y, sum);
+                    }
+                }
+            }
+            res.setAov(aov.name(), film);
+        }
+
+        return res;
+    }
+}
+
+// 一个tile里的样本splat出去的结果。filter半径大于0.5的时候会影响到tile外面的pixel，所以范围要比tile往外扩一圈
+pub(crate) struct Splats {
+    pub(crate) x: usize,
+    pub(crate) y: usize,
+    pub(crate) sums: Vec<Vec<Vec3>>,
+    pub(crate) weights: Vec<Vec<f64>>,
+    pub(crate) aovs: Vec<Vec<Vec<Vec3>>>, // AOV只加到样本自己所在的pixel上，用的是同样的范围
+}
+
+// 一个渲染完的tile，index是它在tiles()里的位置
+pub(crate) struct TileResult {
+    pub(crate) index: usize,
+    pub(crate) pixels: Vec<Vec<PixelStatistics>>,
+    pub(crate) splats: Splats,
+}
+
+impl TileResult {
+    // 这个tile新采了多少个样本，statistics是渲染这个tile之前的
+    // 每个pixel的样本数不会比原来少，读进来的时候检查过了
+    pub(crate) fn sampleCount(&self, tiles: &[Tile], statistics: &[Vec<PixelStatistics>]) -> usize {
+        let tile = &tiles[self.index];
+        let mut res = 0;
+        for (j, row) in self.pixels.iter().enumerate() {
+            for (i, pixel) in row.iter().enumerate() {
+                res += pixel.sampleCount() - statistics[tile.y() + j][tile.x() + i].sampleCount();
+            }
+        }
+        return res;
+    }
+}
+
+// 每个example里都复制粘贴了一遍channel + 多线程的代码，干脆收到库里来
+#[derive(Clone)]
+pub struct Renderer {
+    camera: Arc<dyn Camera>,
+    integrator: Arc<dyn Integrator>,
+    environment: Arc<dyn Environment>, // 光线什么都没打到的时候看到的东西
+    sampler: Arc<dyn Sampler>,         // 用哪种sampler，每个线程用seed复制一个出来
+    filter: Arc<dyn Filter>,           // 样本怎么分配到周围的pixel上
+    width: usize,
+    height: usize,
+    subPixelSampleCount: usize, // 每个pixel细分成多少个sub pixel，自适应采样的时候是上限
+    minSampleCount: usize,      // 自适应采样的时候每个pixel至少采样多少次
+    errorThreshold: f64,        // 相对误差小于这个值的pixel就不再采样了，0表示不用自适应采样
+    timeBudget: Option<Duration>, // 最多渲染多长时间，None表示不限
+    targetNoise: f64,           // 整个画面的平均相对误差小于这个值就不再采样了，0表示不限
+    maxDepth: usize,            // 光线最多弹射多少次
+    tileSize: usize,
+    threadCount: usize,
+    seed: u64,               // 同一个seed渲染出来的图每次都一样，和线程数无关
+    aovs: Vec<Aov>,          // 除了颜色还要输出哪些东西
+    region: Tile,            // 只渲染画面里的这一块，外面是黑的。默认是整个画面
+    sceneHash: u64, // 场景、环境、积分器的参数这些Renderer看不出来的东西，由调用的人自己算一个，checkpoint和分布式渲染的时候对一下
+    workerTimeout: Duration, // 分布式渲染的时候worker多久没动静就当它断了，要比渲染一个tile的时间长
+    #[allow(clippy::type_complexity)]
+    progress: Option<Arc<dyn Fn(&Progress) + Send + Sync>>, // 每渲染完一个tile调用一次
+    cancellationToken: CancellationToken,
+}
+
+pub struct RendererBuilder {
+    renderer: Renderer,
+}
+
+impl RendererBuilder {
+    pub fn build(self) -> Renderer {
+        return self.renderer;
+    }
+
+    pub fn integrator(mut self, integrator: Arc<dyn Integrator>) -> Self {
+        self.renderer.integrator = integrator;
+        return self;
+    }
+
+    pub fn environment(mut self, environment: Arc<dyn Environment>) -> Self {
+        self.renderer.environment = environment;
+        return self;
+    }
+
+    pub fn sampler(mut self, sampler: Arc<dyn Sampler>) -> Self {
+        self.renderer.sampler = sampler;
+        return self;
+    }
+
+    pub fn filter(mut self, filter: Arc<dyn Filter>) -> Self {
+        self.renderer.filter = filter;
+        return self;
+    }
+
+    pub fn subPixelSampleCount(mut self, subPixelSampleCount: usize) -> Self {
+        self.renderer.subPixelSampleCount = subPixelSampleCount;
+        return self;
+    }
+
+    pub fn minSampleCount(mut self, minSampleCount: usize) -> Self {
+        self.renderer.minSampleCount = minSampleCount.max(2);
+        return self;
+    }
+
+    pub fn errorThreshold(mut self, errorThreshold: f64) -> Self {
+        self.renderer.errorThreshold = errorThreshold.max(0.0);
+        return self;
+    }
+
+    pub fn timeBudget(mut self, timeBudget: Duration) -> Self {
+        self.renderer.timeBudget = Some(timeBudget);
+        return self;
+    }
+
+    pub fn targetNoise(mut self, targetNoise: f64) -> Self {
+        self.renderer.targetNoise = targetNoise.max(0.0);
+        return self;
+    }
+
+    pub fn maxDepth(mut self, maxDepth: usize) -> Self {
+        self.renderer.maxDepth = maxDepth;
+        return self;
+    }
+
+    pub fn tileSize(mut self, tileSize: usize) -> Self {
+        self.renderer.tileSize = tileSize.max(1);
+        return self;
+    }
+
+    pub fn threadCount(mut self, threadCount: usize) -> Self {
+        self.renderer.threadCount = threadCount.max(1);
+        return self;
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.renderer.seed = seed;
+        return self;
+    }
+
+    pub fn workerTimeout(mut self, workerTimeout: Duration) -> Self {
+        // 0在set_read_timeout()里是不合法的
+        self.renderer.workerTimeout = workerTimeout.max(Duration::from_millis(1));
+        return self;
+    }
+
+    pub fn aovs(mut self, aovs: Vec<Aov>) -> Self {
+        self.renderer.aovs = aovs;
+        return self;
+    }
+
+    // 超出画面的部分会被裁掉
+    pub fn region(mut self, x: usize, y: usize, width: usize, height: usize) -> Self {
+        let x = x.min(self.renderer.width);
+        let y = y.min(self.renderer.height);
+        self.renderer.region = Tile::new(
+            x,
+            y,
+            width.min(self.renderer.width - x),
+            height.min(self.renderer.height - y),
+        );
+        return self;
+    }
+
+    pub fn sceneHash(mut self, sceneHash: u64) -> Self {
+        self.renderer.sceneHash = sceneHash;
+        return self;
+    }
+
+    // 在渲染的那个线程上调用，不要在里面做太久
+    pub fn progress(mut self, progress: Arc<dyn Fn(&Progress) + Send + Sync>) -> Self {
+        self.renderer.progress = Some(progress);
+        return self;
+    }
+
+    pub fn cancellationToken(mut self, cancellationToken: CancellationToken) -> Self {
+        self.renderer.cancellationToken = cancellationToken;
+        return self;
+    }
+}
+
+impl Renderer {
+    pub fn builder(camera: Arc<dyn Camera>, width: usize, height: usize) -> RendererBuilder {
+        RendererBuilder {
+            renderer: Renderer {
+                camera: camera,
+                integrator: Arc::new(PathTracer::default()),
+                environment: Arc::new(ConstantEnvironment::black()),
+                sampler: Arc::new(RandomSampler::new(0)),
+                filter: Arc::new(BoxFilter::default()),
+                width: width,
+                height: height,
+                subPixelSampleCount: 100,
+                minSampleCount: 16,
+                errorThreshold: 0.0,
+                timeBudget: None,
+                targetNoise: 0.0,
+                maxDepth: 100, // 原来examples里都是100
+                tileSize: 16,
+                threadCount: std::thread::available_parallelism()
+                    .map(|v| v.get())
+                    .unwrap_or(1),
+                seed: 0,
+                aovs: vec![],
+                region: Tile::new(0, 0, width, height),
+                sceneHash: 0,
+                workerTimeout: Duration::from_secs(600),
+                progress: None,
+                cancellationToken: CancellationToken::new(),
+            },
+        }
+    }
+
+    pub fn new(
+        camera: Arc<dyn Camera>,
+        width: usize,
+        height: usize,
+        subPixelSampleCount: usize,
+        maxDepth: usize,
+    ) -> Self {
+        Self::builder(camera, width, height)
+            .subPixelSampleCount(subPixelSampleCount)
+            .maxDepth(maxDepth)
+            .build()
+    }
+
+    pub fn camera(&self) -> &Arc<dyn Camera> {
+        return &self.camera;
+    }
+
+    pub fn integrator(&self) -> &Arc<dyn Integrator> {
+        return &self.integrator;
+    }
+
+    pub fn environment(&self) -> &Arc<dyn Environment> {
+        return &self.environment;
+    }
+
+    pub fn sampler(&self) -> &Arc<dyn Sampler> {
+        return &self.sampler;
+    }
+
+    pub fn filter(&self) -> &Arc<dyn Filter> {
+        return &self.filter;
+    }
+
+    pub fn width(&self) -> usize {
+        return self.width;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.height;
+    }
+
+    pub fn subPixelSampleCount(&self) -> usize {
+        return self.subPixelSampleCount;
+    }
+
+    pub fn minSampleCount(&self) -> usize {
+        return self.minSampleCount;
+    }
+
+    pub fn errorThreshold(&self) -> f64 {
+        return self.errorThreshold;
+    }
+
+    pub fn timeBudget(&self) -> Option<Duration> {
+        return self.timeBudget;
+    }
+
+    pub fn targetNoise(&self) -> f64 {
+        return self.targetNoise;
+    }
+
+    pub fn maxDepth(&self) -> usize {
+        return self.maxDepth;
+    }
+
+    pub fn tileSize(&self) -> usize {
+        return self.tileSize;
+    }
+
+    pub fn threadCount(&self) -> usize {
+        return self.threadCount;
+    }
+
+    pub fn seed(&self) -> u64 {
+        return self.seed;
+    }
+
+    pub fn workerTimeout(&self) -> Duration {
+        return self.workerTimeout;
+    }
+
+    pub fn aovs(&self) -> &Vec<Aov> {
+        return &self.aovs;
+    }
+
+    pub fn region(&self) -> &Tile {
+        return &self.region;
+    }
+
+    pub fn sceneHash(&self) -> u64 {
+        return self.sceneHash;
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub fn progress(&self) -> &Option<Arc<dyn Fn(&Progress) + Send + Sync>> {
+        return &self.progress;
+    }
+
+    pub fn cancellationToken(&self) -> &CancellationToken {
+        return &self.cancellationToken;
+    }
+
+    // 把要渲染的区域切成tileSize x tileSize的小块，最右边和最上面的块可能小一点
+    pub fn tiles(&self) -> Vec<Tile> {
+        let mut res = vec![];
+        let region = &self.region;
+        let right = region.x() + region.width();
+        let top = region.y() + region.height();
+
+        for y in (region.y()..top).step_by(self.tileSize) {
+            for x in (region.x()..right).step_by(self.tileSize) {
+                res.push(Tile::new(
+                    x,
+                    y,
+                    self.tileSize.min(right - x),
+                    self.tileSize.min(top - y),
+                ));
+            }
+        }
+
+        return res;
+    }
+
+    // 返回的film和原来的buffer一样，y = 0是画面最下面一行
+    // 设置了aovs的话，film.aov(aov.name())里是对应的结果
+    // 设了timeBudget或者targetNoise的话，要一遍一遍地渲染才能随时停下来，和renderProgressive()一样
+    // 取消了的话，返回的film里只有已经渲染完的tile，其他地方是黑的
+    pub fn render(&self, world: &dyn Hit) -> Film {
+        if self.timeBudget.is_some() || self.targetNoise > 0.0 {
+            return self.renderProgressive(world, |_, _| {});
+        }
+
+        let mut accumulation = Accumulation::new(self.width, self.height, self.aovs.len());
+        let mut progress = Progress::new(self.tiles().len(), None);
+        self.renderPass(
+            world,
+            self.subPixelSampleCount,
+            &mut accumulation,
+            vec![],
+            &mut progress,
+            &mut |_, _| {},
+        );
+        return accumulation.film(&self.aovs);
+    }
+
+    // 渐进式渲染：每一遍整个画面每个pixel只采样一次，累加起来，最多subPixelSampleCount遍
+    // 每一遍结束都把当前的平均值交给callback，第一个参数是已经完成了几遍，这样几秒钟就能看到一张粗糙的图，然后越来越清楚
+    // 开了自适应采样的话，所有pixel都收敛了就提前结束；整个画面的噪点到了targetNoise也提前结束
+    // 设了timeBudget的话，估计下一遍做不完就不做了，所以一般不会超时，但是第一遍无论如何都要做完
+    // 每个pixel最后采了多少个样本可以用Aov::SampleCount输出
+    // 取消了的话返回目前为止的结果，最后那一遍可能只有一部分tile
+    pub fn renderProgressive<F>(&self, world: &dyn Hit, mut callback: F) -> Film
+    where
+        F: FnMut(usize, &Film),
+    {
+        let mut accumulation = Accumulation::new(self.width, self.height, self.aovs.len());
+        let mut film = accumulation.film(&self.aovs);
+        let start = Instant::now();
+        let tileCount = self.tiles().len() * self.subPixelSampleCount;
+        let mut progress = Progress::new(tileCount, self.timeBudget);
+
+        for pass in 1..=self.subPixelSampleCount {
+            let passStart = Instant::now();
+            let sampleCount = self.renderPass(
+                world,
+                1,
+                &mut accumulation,
+                vec![],
+                &mut progress,
+                &mut |_, _| {},
+            );
+            if sampleCount == 0 {
+                break;
+            }
+
+            film = accumulation.film(&self.aovs);
+            if self.cancellationToken.isCancelled() {
+                break;
+            }
+            callback(pass, &film);
+
+            if self.targetNoise > 0.0 && self.noise(&accumulation) <= self.targetNoise {
+                break;
+            }
+            if let Some(timeBudget) = self.timeBudget {
+                if start.elapsed() + passStart.elapsed() > timeBudget {
+                    break;
+                }
+            }
+        }
+
+        return film;
+    }
+
+    // region里所有pixel相对误差的平均值，还有pixel不到两个样本的话是无穷大
+    fn noise(&self, accumulation: &Accumulation) -> f64 {
+        let region = &self.region;
+        let mut sum = 0.0;
+        for row in &accumulation.statistics[region.y()..region.y() + region.height()] {
+            for statistics in &row[region.x()..region.x() + region.width()] {
+                sum += statistics.relativeError();
+            }
+        }
+        return sum / (region.width() * region.height()).max(1) as f64;
+    }
+
+    // checkpoint和分布式渲染每个tile都是一次渲染完的，没法按时间或者噪点中途停下来
+    pub(crate) fn checkSinglePass(&self) -> Result<()> {
+        if self.timeBudget.is_some() || self.targetNoise > 0.0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "timeBudget and targetNoise only work with render() and renderProgressive()",
+            ));
+        }
+        return Ok(());
+    }
+
+    // 这个pixel还需不需要继续采样
+    fn converged(&self, statistics: &PixelStatistics) -> bool {
+        if statistics.sampleCount() >= self.subPixelSampleCount {
+            return true;
+        }
+        return self.errorThreshold > 0.0
+            && statistics.sampleCount() >= self.minSampleCount
+            && statistics.relativeError() <= self.errorThreshold;
+    }
+
+    // 整个画面每个没收敛的pixel再最多采样sampleCount次，返回这一遍一共采了多少个样本
+    // done是这一遍里已经渲染好的tile（从checkpoint读回来的），不用再渲染
+    // 每渲染完一个tile，就把这一遍开始时候的accumulation和目前所有渲染好的tile交给onResult，取消了的话最后再交一次
+    // 用scoped thread的好处是world不需要是Arc了，传个引用进来就可以
+    pub(crate) fn renderPass(
+        &self,
+        world: &dyn Hit,
+        sampleCount: usize,
+        accumulation: &mut Accumulation,
+        done: Vec<TileResult>,
+        progress: &mut Progress,
+        onResult: &mut dyn FnMut(&Accumulation, &[TileResult]),
+    ) -> usize {
+        let tiles = self.tiles();
+        let remaining: Vec<usize> = (0..tiles.len())
+            .filter(|i| !done.iter().any(|result| result.index == *i))
+            .collect();
+        let next = AtomicUsize::new(0); // 下一个没人领的tile
+        let mut results = done;
+
+        std::thread::scope(|scope| {
+            let (sender, receiver) = channel();
+
+            for _ in 0..self.threadCount {
+                let sender = sender.clone();
+                let tiles = &tiles;
+                let remaining = &remaining;
+                let next = &next;
+                let statistics = &accumulation.statistics;
+
+                scope.spawn(move || loop {
+                    if self.cancellationToken.isCancelled() {
+                        break;
+                    }
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    if i >= remaining.len() {
+                        break;
+                    }
+
+                    let index = remaining[i];
+                    let (pixels, splats) =
+                        self.renderTile(world, &tiles[index], sampleCount, statistics);
+                    sender
+                        .send(TileResult {
+                            index: index,
+                            pixels: pixels,
+                            splats: splats,
+                        })
+                        .unwrap();
+                });
+            }
+
+            drop(sender); // 不然下面的for永远不会结束
+
+            for result in receiver {
+                progress.add(result.sampleCount(&tiles, &accumulation.statistics));
+                if let Some(callback) = &self.progress {
+                    callback(progress);
+                }
+
+                results.push(result);
+                onResult(accumulation, &results);
+            }
+
+            if self.cancellationToken.isCancelled() {
+                onResult(accumulation, &results);
+            }
+        });
+
+        return self.merge(accumulation, results);
+    }
+
+    // 把一遍渲染完的所有tile加到accumulation上，返回一共多了多少个样本
+    pub(crate) fn merge(
+        &self,
+        accumulation: &mut Accumulation,
+        mut results: Vec<TileResult>,
+    ) -> usize {
+        let tiles = self.tiles();
+
+        // 相邻的tile会splat到同一个pixel上，按tile的顺序加起来，不然浮点数加法的顺序不一样，结果就不是每次都一样了
+        results.sort_by_key(|result| result.index);
+
+        let mut res = 0;
+        for result in results {
+            let tile = &tiles[result.index];
+            for (j, row) in result.pixels.into_iter().enumerate() {
+                for (i, pixel) in row.into_iter().enumerate() {
+                    let old = &mut accumulation.statistics[tile.y() + j][tile.x() + i];
+                    res += pixel.sampleCount() - old.sampleCount();
+                    *old = pixel;
+                }
+            }
+
+            let splats = &result.splats;
+            for (j, (sums, weights)) in splats.sums.iter().zip(splats.weights.iter()).enumerate() {
+                for (i, (sum, weight)) in sums.iter().zip(weights.iter()).enumerate() {
+                    accumulation.sums[splats.y + j][splats.x + i] += *sum;
+                    accumulation.weights[splats.y + j][splats.x + i] += *weight;
+                }
+            }
+
+            for (aov, sums) in accumulation.aovs.iter_mut().zip(splats.aovs.iter()) {
+                for (j, row) in sums.iter().enumerate() {
+                    for (i, sum) in row.iter().enumerate() {
+                        aov[splats.y + j][splats.x + i] += *sum;
+                    }
+                }
+            }
+        }
+
+        return res;
+    }
+
+    // 返回这个tile里每个pixel加上新样本之后的统计，和这些新样本splat出去的结果
+    pub(crate) fn renderTile(
+        &self,
+        world: &dyn Hit,
+        tile: &Tile,
+        sampleCount: usize,
+        statistics: &[Vec<PixelStatistics>],
+    ) -> (Vec<Vec<PixelStatistics>>, Splats) {
+        let mut res = vec![vec![PixelStatistics::new(); tile.width()]; tile.height()];
+        let mut sampler = self.sampler.seeded(self.seed);
+
+        // 只splat到要渲染的区域里面
+        let region = &self.region;
+        let padding = (self.filter.radius() - 0.5).max(0.0).ceil() as usize;
+        let x = tile.x().saturating_sub(padding).max(region.x());
+        let y = tile.y().saturating_sub(padding).max(region.y());
+        let width = (tile.x() + tile.width() + padding).min(region.x() + region.width()) - x;
+        let height = (tile.y() + tile.height() + padding).min(region.y() + region.height()) - y;
+        let mut splats = Splats {
+            x: x,
+            y: y,
+            sums: vec![vec![Vec3::new(0.0, 0.0, 0.0); width]; height],
+            weights: vec![vec![0.0; width]; height],
+            aovs: vec![vec![vec![Vec3::new(0.0, 0.0, 0.0); width]; height]; self.aovs.len()],
+        };
+
+        for (j, row) in res.iter_mut().enumerate() {
+            for (i, pixel) in row.iter_mut().enumerate() {
+                let (x, y) = (tile.x() + i, tile.y() + j);
+                *pixel = statistics[y][x];
+                self.renderPixel(
+                    world,
+                    (x, y),
+                    sampleCount,
+                    pixel,
+                    &mut splats,
+                    sampler.as_mut(),
+                );
+            }
+        }
+
+        return (res, splats);
+    }
+
+    fn renderPixel(
+        &self,
+        world: &dyn Hit,
+        (x, y): (usize, usize),
+        sampleCount: usize,
+        statistics: &mut PixelStatistics,
+        splats: &mut Splats,
+        sampler: &mut dyn Sampler,
+    ) {
+        for _ in 0..sampleCount {
+            if self.converged(statistics) {
+                break;
+            }
+
+            // 第几个样本接着这个pixel已经有的样本数往下数，渐进式渲染的时候也是一样的随机数
+            sampler.startPixelSample((x, y), statistics.sampleCount());
+            let (du, dv) = sampler.next2D();
+            let u = (x as f64 + du) / self.width as f64;
+            let v = (y as f64 + dv) / self.height as f64;
+            // 被镜头挡住的样本也要算数，只是黑的。什么都没看到，所以不算AOV
+            let (ray, weight) = match self.camera.weightedRay(u, v, sampler) {
+                Some(v) => v,
+                None => {
+                    statistics.addBlocked();
+                    let black = Vec3::new(0.0, 0.0, 0.0);
+                    self.splat(splats, (x as f64 + du, y as f64 + dv), black);
+                    continue;
+                }
+            };
+            let (color, record) = self.integrator.radianceAndFirstHit(
+                &ray,
+                world,
+                self.environment.as_ref(),
+                self.maxDepth,
+                sampler,
+            );
+            self.addAovs(record.as_ref(), (x, y), statistics.unblockedCount(), splats);
+            let color = color * weight;
+            statistics.add(color);
+            self.splat(splats, (x as f64 + du, y as f64 + dv), color);
+        }
+    }
+
+    // record是相机光线第一次打到的地方，index是这个pixel的第几个没被挡住的样本
+    fn addAovs(
+        &self,
+        record: Option<&HitRecord>,
+        (x, y): (usize, usize),
+        index: usize,
+        splats: &mut Splats,
+    ) {
+        for (aov, sums) in self.aovs.iter().zip(splats.aovs.iter_mut()) {
+            if *aov == Aov::SampleCount {
+                continue;
+            }
+            if aov.isAveraged() || index == 0 {
+                sums[y - splats.y][x - splats.x] += aov.value(record);
+            }
+        }
+    }
+
+    // 样本在画面上的位置是point，加到中心离它不超过filter半径的所有pixel上
+    // 区间是左闭右开的，这样box filter的时候每个样本正好只算到自己所在的pixel里
+    fn splat(&self, splats: &mut Splats, point: (f64, f64), color: Vec3) {
+        let radius = self.filter.radius();
+        let range = |center: f64, start: usize, length: usize| -> Range<usize> {
+            let low = ((center - 0.5 - radius).floor() as isize + 1).max(start as isize);
+            let high =
+                ((center - 0.5 + radius).floor() as isize + 1).min((start + length) as isize);
+            return low as usize..high.max(low) as usize;
+        };
+        let xs = range(point.0, splats.x, splats.sums[0].len());
+        let ys = range(point.1, splats.y, splats.sums.len());
+
+        for py in ys {
+            for px in xs.clone() {
+                let weight = self
+                    .filter
+                    .evaluate(point.0 - px as f64 - 0.5, point.1 - py as f64 - 0.5);
+                if weight != 0.0 {
+                    splats.sums[py - splats.y][px - splats.x] += color * weight;
+                    splats.weights[py - splats.y][px - splats.x] += weight;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::aov::Aov;
+    use crate::camera::Camera;
+    use crate::camera::PerspectiveCamera;
+    use crate::environment::ConstantEnvironment;
+    use crate::environment::GradientEnvironment;
+    use crate::film::Film;
+    use crate::filter::MitchellFilter;
+    use crate::geometry::Sphere;
+    use crate::material::Lambertian;
+    use crate::ray::Hit;
+    use crate::ray::HitRecord;
+    use crate::ray::Ray;
+    use crate::render::CancellationToken;
+    use crate::render::Progress;
+    use crate::render::Renderer;
+    use crate::render::RendererBuilder;
+    use crate::render::Tile;
+    use crate::sampler::Sampler;
+    use crate::sprite::Sprite;
+    use crate::vec3::Vec3;
+
+    use std::io::ErrorKind;
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    // 大部分测试用的场景：相机在z = 5看着原点，有一点景深
+    pub(crate) fn testCamera() -> PerspectiveCamera {
+        return PerspectiveCamera::new(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::ey(),
+            (40.0 as f64).to_radians(),
+            1.0,
+            5.0,
+            0.1,
+        );
+    }
+
+    // 原点上一个灰色的单位球
+    pub(crate) fn testScene() -> Sprite<Sphere, Lambertian> {
+        return Sprite::new(
+            Some(Arc::new(Sphere::new(1.0))),
+            Some(Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)))),
+        );
+    }
+
+    // 天空是均匀的浅蓝色，每个pixel 4个样本，4 x 4的tile，测试在这上面改自己要的设置
+    pub(crate) fn testRenderer(width: usize, height: usize) -> RendererBuilder {
+        return Renderer::builder(Arc::new(testCamera()), width, height)
+            .environment(Arc::new(ConstantEnvironment::new(Vec3::new(0.5, 0.7, 1.0))))
+            .subPixelSampleCount(4)
+            .tileSize(4);
+    }
+
+    // 背景是均匀的灰色，每个样本都一样，采到minSampleCount次就应该都收敛了
+    #[test]
+    fn adaptiveSamplingStopsOnFlatBackground() {
+        let camera = PerspectiveCamera::new(
+            Vec3::new(0.0, 0.0, 5.0),
+            Vec3::new(0.0, 0.0, 10.0), // 背对着球
+            Vec3::ey(),
+            (40.0 as f64).to_radians(),
+            1.0,
+            1.0,
+            0.0,
+        );
+        let renderer = Renderer::builder(Arc::new(camera), 8, 8)
+            .environment(Arc::new(ConstantEnvironment::new(Vec3::new(0.5, 0.5, 0.5))))
+            .subPixelSampleCount(100)
+            .minSampleCount(4)
+            .errorThreshold(0.01)
+            .build();
+
+        let mut passCount = 0;
+        let film = renderer.renderProgressive(&Sphere::new(1.0), |pass, _| passCount = pass);
+        assert_eq!(passCount, 4);
+        assert_eq!(film.pixel(5, 3).g(), 0.5);
+    }
+
+    // 噪点够小了或者时间到了就不再加样本，每个pixel的样本数从Aov::SampleCount里能看到
+    #[test]
+    fn progressiveStopsAtTargetNoiseOrTimeBudget() {
+        let world = testScene();
+        let build = || -> RendererBuilder {
+            return testRenderer(8, 8)
+                .environment(Arc::new(GradientEnvironment::new(
+                    Vec3::new(1.0, 1.0, 1.0),
+                    Vec3::new(0.5, 0.7, 1.0),
+                )))
+                .subPixelSampleCount(256)
+                .aovs(vec![Aov::SampleCount]);
+        };
+        let sampleCounts = |film: &Film| -> Vec<f64> {
+            let counts = film.aov(Aov::SampleCount.name()).unwrap();
+            return counts.pixels().iter().map(|v| v[0] as f64).collect();
+        };
+
+        let renderer = build().targetNoise(0.02).build();
+        let mut passes = 0;
+        let film = renderer.renderProgressive(&world, |pass, _| passes = pass);
+        assert!(passes > 2 && passes < 256);
+        assert!(sampleCounts(&film).iter().all(|v| *v == passes as f64));
+        assert_eq!(renderer.render(&world), film);
+
+        let renderer = build().timeBudget(Duration::ZERO).build();
+        let film = renderer.renderProgressive(&world, |pass, _| passes = pass);
+        assert_eq!(passes, 1);
+        assert!(sampleCounts(&film).iter().all(|v| *v == 1.0));
+
+        // checkpoint和分布式渲染没法中途停下来，直接报错
+        let path =
+            std::env::temp_dir().join(format!("ray-tracer-budget-{}.bin", std::process::id()));
+        let error = renderer
+            .renderWithCheckpoint(&world, &path, Duration::ZERO)
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(!path.exists());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let error = renderer.renderDistributed(listener).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        let error = renderer.renderWorker(&world, address).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+
+    // 同一个seed，不管几个线程、是不是渐进式渲染，结果都一模一样
+    #[test]
+    fn renderIsReproducible() {
+        let world = testScene();
+        let build = |threadCount: usize, seed: u64| -> RendererBuilder {
+            return testRenderer(8, 8)
+                .environment(Arc::new(GradientEnvironment::new(
+                    Vec3::new(1.0, 1.0, 1.0),
+                    Vec3::new(0.5, 0.7, 1.0),
+                )))
+                .tileSize(3)
+                .threadCount(threadCount)
+                .seed(seed);
+        };
+
+        let expected = build(1, 42).build().render(&world);
+        assert_eq!(build(4, 42).build().render(&world), expected);
+        assert_eq!(
+            build(3, 42).build().renderProgressive(&world, |_, _| {}),
+            expected
+        );
+        assert_ne!(build(4, 43).build().render(&world), expected);
+
+        // filter半径大于0.5的时候样本会splat到相邻的tile里
+        let filter = Arc::new(MitchellFilter::default());
+        let expected = build(1, 42).filter(filter.clone()).build().render(&world);
+        assert_eq!(build(4, 42).filter(filter).build().render(&world), expected);
+    }
+
+    // 每一遍结束回调一次，第k遍的图和直接每个pixel采k个样本的一样，最后一遍就是render()的结果
+    #[test]
+    fn progressiveCallsBackEveryPass() {
+        let world = testScene();
+        let build = |subPixelSampleCount: usize| -> Renderer {
+            return testRenderer(6, 6)
+                .subPixelSampleCount(subPixelSampleCount)
+                .build();
+        };
+
+        let mut passes = vec![];
+        let film = build(3).renderProgressive(&world, |pass, film| {
+            assert_eq!(*film, build(pass).render(&world));
+            passes.push(pass);
+        });
+        assert_eq!(passes, vec![1, 2, 3]);
+        assert_eq!(film, build(3).render(&world));
+    }
+
+    // 正对着球心的pixel，深度差不多是相机到球面的距离，法向量朝着相机。开不开AOV颜色都一样
+    #[test]
+    fn aovsSeeFirstHit() {
+        let world = Sprite::builder()
+            .geometry(Arc::new(Sphere::new(1.0)))
+            .material(Arc::new(Lambertian::new(Vec3::new(0.2, 0.4, 0.6))))
+            .objectId(7)
+            .build();
+        let film = testRenderer(9, 9)
+            .aovs(vec![Aov::Depth, Aov::Normal, Aov::Albedo, Aov::ObjectId])
+            .build()
+            .render(&world);
+        let aov = |aov: Aov, x: usize, y: usize| -> Vec3 {
+            return film.aov(aov.name()).unwrap().pixel(x, y);
+        };
+
+        // 一个pixel有4度多，样本不在正中间，所以差一点
+        assert!((aov(Aov::Depth, 4, 4).x() - 4.0).abs() < 0.05);
+        assert!(aov(Aov::Normal, 4, 4).z() > 0.95);
+        assert!((aov(Aov::Albedo, 4, 4) - Vec3::new(0.2, 0.4, 0.6)).length() < 1e-6);
+        assert_eq!(aov(Aov::ObjectId, 4, 4).x(), 7.0);
+
+        // 角落里什么都没打到
+        assert_eq!(aov(Aov::Depth, 0, 0).x(), 0.0);
+        assert_eq!(aov(Aov::ObjectId, 0, 0).x(), 0.0);
+        assert_eq!(film.aov(Aov::Uv.name()), None);
+
+        assert_eq!(
+            testRenderer(9, 9).build().render(&world).pixels(),
+            film.pixels()
+        );
+    }
+
+    // 被镜头挡住的样本不算AOV：每个pixel的第一个样本都被挡住了，深度用的是第一个没被挡住的样本，法向量的平均也不会被拉低
+    #[test]
+    fn aovsSkipBlockedSamples() {
+        // 只有一个线程的话，每个pixel的第1、3个样本被挡住
+        struct Blocking {
+            camera: PerspectiveCamera,
+            count: AtomicUsize,
+        }
+
+        impl Camera for Blocking {
+            fn ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Ray {
+                return self.camera.ray(u, v, sampler);
+            }
+
+            fn weightedRay(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Option<(Ray, f64)> {
+                if self.count.fetch_add(1, Ordering::SeqCst) % 2 == 0 {
+                    return None;
+                }
+                return self.camera.weightedRay(u, v, sampler);
+            }
+        }
+
+        let camera = Blocking {
+            camera: testCamera(),
+            count: AtomicUsize::new(0),
+        };
+        let film = Renderer::builder(Arc::new(camera), 9, 9)
+            .subPixelSampleCount(4)
+            .threadCount(1)
+            .aovs(vec![Aov::Depth, Aov::Normal, Aov::SampleCount])
+            .build()
+            .render(&testScene());
+        let aov = |aov: Aov, x: usize, y: usize| -> Vec3 {
+            return film.aov(aov.name()).unwrap().pixel(x, y);
+        };
+
+        assert!((aov(Aov::Depth, 4, 4).x() - 4.0).abs() < 0.05);
+        assert!(aov(Aov::Normal, 4, 4).z() > 0.95);
+        assert_eq!(aov(Aov::SampleCount, 4, 4).x(), 4.0);
+    }
+
+    // 只渲染一块的时候，box filter下这一块和整张图渲染出来的一样，外面是黑的
+    #[test]
+    fn regionMatchesFullRender() {
+        let world = testScene();
+        let full = testRenderer(12, 10).build().render(&world);
+        let region = testRenderer(12, 10).region(3, 2, 7, 100).build();
+        assert_eq!(region.region(), &Tile::new(3, 2, 7, 8));
+
+        let film = region.render(&world);
+        assert_eq!(film.crop(3, 2, 7, 8), full.crop(3, 2, 7, 8));
+        assert_eq!(film.pixel(2, 5), Vec3::new(0.0, 0.0, 0.0));
+        assert_eq!(film.pixel(5, 1), Vec3::new(0.0, 0.0, 0.0));
+    }
+
+    // 进度每个tile报一次；取消了以后正在渲染的tile会渲染完，不再开始新的tile
+    #[test]
+    fn progressAndCancellation() {
+        struct Cancelling<'a> {
+            world: &'a dyn Hit,
+            token: CancellationToken,
+        }
+
+        // 第一次求交的时候就取消，只有一个线程的话正好渲染完第一个tile
+        impl Hit for Cancelling<'_> {
+            fn hit(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<HitRecord> {
+                self.token.cancel();
+                return self.world.hit(ray, sampler);
+            }
+        }
+
+        let world = testScene();
+        let reports = Arc::new(Mutex::new(vec![]));
+        let build = |token: &CancellationToken| -> Renderer {
+            let reports = reports.clone();
+            return testRenderer(12, 12)
+                .threadCount(1)
+                .progress(Arc::new(move |progress: &Progress| {
+                    reports.lock().unwrap().push(*progress);
+                }))
+                .cancellationToken(token.clone())
+                .build();
+        };
+
+        let expected = build(&CancellationToken::new()).render(&world);
+        let last = *reports.lock().unwrap().last().unwrap();
+        assert_eq!(reports.lock().unwrap().len(), 9);
+        assert_eq!((last.tilesDone(), last.tileCount()), (9, 9));
+        assert_eq!(last.sampleCount(), 12 * 12 * 4);
+        assert_eq!(last.eta(), Some(Duration::ZERO));
+
+        reports.lock().unwrap().clear();
+        let token = CancellationToken::new();
+        let cancelling = Cancelling {
+            world: &world,
+            token: token.clone(),
+        };
+        let renderer = build(&token);
+        let film = renderer.render(&cancelling);
+        assert_eq!(reports.lock().unwrap().len(), 1);
+        assert_eq!(film.crop(0, 0, 4, 4), expected.crop(0, 0, 4, 4));
+        assert_eq!(film.pixel(11, 11), Vec3::new(0.0, 0.0, 0.0));
+
+        // reset()以后同一个Renderer还能接着用
+        token.reset();
+        assert!(!renderer.cancellationToken().isCancelled());
+        assert_eq!(renderer.render(&world), expected);
+
+        // 取消的时候checkpoint会存下来，下次接着渲染
+        let path =
+            std::env::temp_dir().join(format!("ray-tracer-cancel-{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let token = CancellationToken::new();
+        let cancelling = Cancelling {
+            world: &world,
+            token: token.clone(),
+        };
+        let error = build(&token)
+            .renderWithCheckpoint(&cancelling, &path, Duration::from_secs(3600))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Interrupted);
+        assert!(path.exists());
+
+        let film = build(&CancellationToken::new())
+            .renderWithCheckpoint(&world, &path, Duration::from_secs(3600))
+            .unwrap();
+        assert_eq!(film, expected);
     }
 }
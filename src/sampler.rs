@@ -0,0 +1,494 @@
+// 渲染里所有的随机数都从Sampler里拿，不再用thread_rng()，这样同一个seed渲染出来的图每次都一模一样
+// 每个pixel的每个样本开始之前调用startPixelSample()，随机数只由seed、pixel坐标和第几个样本决定，
+// 和哪个线程、按什么顺序渲染都没有关系
+// 一个样本里第几次调用next1D()/next2D()就是第几个维度。低差异序列的sampler保证同一个pixel的所有样本在每个维度上都分布得很均匀
+pub trait Sampler: Send + Sync {
+    fn startPixelSample(&mut self, pixel: (usize, usize), index: usize);
+
+    // [0, 1)上的一个随机数
+    fn next1D(&mut self) -> f64;
+
+    fn next2D(&mut self) -> (f64, f64) {
+        let u = self.next1D();
+        let v = self.next1D();
+        return (u, v);
+    }
+
+    // 同一种sampler换一个seed，Renderer每个线程用它复制一个自己的sampler
+    fn seeded(&self, seed: u64) -> Box<dyn Sampler>;
+
+    // 具体是哪一种sampler，checkpoint和分布式渲染的时候用来对一下设置
+    fn name(&self) -> &'static str {
+        return std::any::type_name::<Self>();
+    }
+}
+
+// PCG32，rand库的版本一变，同一个seed出来的数可能就不一样了，所以自己写一个
+// <https://www.pcg-random.org/download.html>
+#[derive(Copy, Clone, Debug)]
+pub struct Pcg32 {
+    state: u64,
+    increment: u64,
+}
+
+impl Pcg32 {
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut res = Self {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        res.nextU32();
+        res.state = res.state.wrapping_add(seed);
+        res.nextU32();
+        return res;
+    }
+
+    pub fn nextU32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(self.increment);
+        let xorShifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rotation = (old >> 59) as u32;
+        return xorShifted.rotate_right(rotation);
+    }
+
+    // [0, 1)，不会取到1
+    pub fn nextF64(&mut self) -> f64 {
+        return self.nextU32() as f64 / 4294967296.0;
+    }
+}
+
+// 把几个数混成一个看起来很随机的64位整数，用的是SplitMix64的finalizer
+pub fn hash(values: &[u64]) -> u64 {
+    let mut res: u64 = 0x9e3779b97f4a7c15;
+    for value in values.iter() {
+        let mut z = res ^ value.wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        res = z ^ (z >> 31);
+    }
+    return res;
+}
+
+// 每个维度都是独立均匀的随机数，和原来thread_rng()的效果一样
+#[derive(Clone, Debug)]
+pub struct RandomSampler {
+    seed: u64,
+    generator: Pcg32,
+}
+
+impl RandomSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed: seed,
+            generator: Pcg32::new(seed, 0),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        return self.seed;
+    }
+}
+
+impl Sampler for RandomSampler {
+    fn startPixelSample(&mut self, pixel: (usize, usize), index: usize) {
+        self.generator = Pcg32::new(
+            hash(&[self.seed, pixel.0 as u64, pixel.1 as u64, index as u64]),
+            self.seed,
+        );
+    }
+
+    fn next1D(&mut self) -> f64 {
+        return self.generator.nextF64();
+    }
+
+    fn seeded(&self, seed: u64) -> Box<dyn Sampler> {
+        return Box::new(Self::new(seed));
+    }
+}
+
+// 64位hash取高53位变成[0, 1)上的数
+fn hashToF64(value: u64) -> f64 {
+    return (value >> 11) as f64 / (1u64 << 53) as f64;
+}
+
+// 把[0, length)里的数随机打乱，p不同打乱的方式就不同，不用真的生成一个数组
+// Kensler的方法 <https://graphics.pixar.com/library/MultiJitteredSampling/paper.pdf>
+fn permutationElement(i: u32, length: u32, p: u32) -> u32 {
+    let mut w = length.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    let mut i = i;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length {
+            break;
+        }
+    }
+
+    return (i.wrapping_add(p)) % length;
+}
+
+// 分层采样：每个维度都把[0, 1)分成xSamples * ySamples层，同一个pixel的样本每层正好一个，层里面再随机抖动
+// 二维的时候分成xSamples x ySamples的格子。样本的顺序在每个维度上都是随机打乱的，不然各个维度之间就相关了
+// 每个pixel的样本数最好正好是xSamples * ySamples，超出去的话又从头开始分层
+#[derive(Clone, Debug)]
+pub struct StratifiedSampler {
+    xSamples: usize,
+    ySamples: usize,
+    seed: u64,
+    pixel: (usize, usize),
+    index: usize,
+    dimension: usize,
+}
+
+impl StratifiedSampler {
+    pub fn new(xSamples: usize, ySamples: usize, seed: u64) -> Self {
+        Self {
+            xSamples: xSamples.max(1),
+            ySamples: ySamples.max(1),
+            seed: seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    pub fn xSamples(&self) -> usize {
+        return self.xSamples;
+    }
+
+    pub fn ySamples(&self) -> usize {
+        return self.ySamples;
+    }
+
+    pub fn seed(&self) -> u64 {
+        return self.seed;
+    }
+
+    // 这个维度上打乱顺序用的hash，同一个pixel所有样本都一样
+    fn dimensionHash(&self) -> u64 {
+        return hash(&[
+            self.seed,
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension as u64,
+        ]);
+    }
+
+    // 层里面的抖动，每个样本都不一样
+    fn jitter(&self, which: u64) -> f64 {
+        return hashToF64(hash(&[
+            self.seed,
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.index as u64,
+            self.dimension as u64,
+            which,
+        ]));
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn startPixelSample(&mut self, pixel: (usize, usize), index: usize) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next1D(&mut self) -> f64 {
+        let count = self.xSamples * self.ySamples;
+        let stratum = permutationElement(
+            (self.index % count) as u32,
+            count as u32,
+            self.dimensionHash() as u32,
+        );
+        let res = (stratum as f64 + self.jitter(0)) / count as f64;
+        self.dimension += 1;
+        return res;
+    }
+
+    fn next2D(&mut self) -> (f64, f64) {
+        let count = self.xSamples * self.ySamples;
+        let stratum = permutationElement(
+            (self.index % count) as u32,
+            count as u32,
+            self.dimensionHash() as u32,
+        ) as usize;
+        let x = ((stratum % self.xSamples) as f64 + self.jitter(0)) / self.xSamples as f64;
+        let y = ((stratum / self.xSamples) as f64 + self.jitter(1)) / self.ySamples as f64;
+        self.dimension += 2;
+        return (x, y);
+    }
+
+    fn seeded(&self, seed: u64) -> Box<dyn Sampler> {
+        return Box::new(Self::new(self.xSamples, self.ySamples, seed));
+    }
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+// index在base进制下的各位数字倒过来放到小数点后面
+fn radicalInverse(base: u64, index: u64) -> f64 {
+    let inverseBase = 1.0 / base as f64;
+    let mut index = index;
+    let mut reversed = 0.0;
+    let mut factor = inverseBase;
+
+    while index > 0 {
+        reversed += (index % base) as f64 * factor;
+        index /= base;
+        factor *= inverseBase;
+    }
+
+    return reversed.min(1.0 - f64::EPSILON);
+}
+
+// Halton序列：第d个维度用第d个质数做进制的radical inverse
+// 每个pixel用的都是同一个序列，所以每个pixel、每个维度再加一个随机的偏移（Cranley-Patterson rotation），超过1就绕回来
+// 维度超过质数表的部分就退化成普通的随机数
+#[derive(Clone, Debug)]
+pub struct HaltonSampler {
+    seed: u64,
+    pixel: (usize, usize),
+    index: usize,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed: seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        return self.seed;
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn startPixelSample(&mut self, pixel: (usize, usize), index: usize) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next1D(&mut self) -> f64 {
+        let pixelHash = hash(&[
+            self.seed,
+            self.pixel.0 as u64,
+            self.pixel.1 as u64,
+            self.dimension as u64,
+        ]);
+
+        let res = if self.dimension < PRIMES.len() {
+            let value =
+                radicalInverse(PRIMES[self.dimension], self.index as u64) + hashToF64(pixelHash);
+            if value >= 1.0 {
+                value - 1.0
+            } else {
+                value
+            }
+        } else {
+            hashToF64(hash(&[pixelHash, self.index as u64]))
+        };
+
+        self.dimension += 1;
+        return res;
+    }
+
+    fn seeded(&self, seed: u64) -> Box<dyn Sampler> {
+        return Box::new(Self::new(seed));
+    }
+}
+
+// Sobol序列前两个维度的生成矩阵，每个数是一列。第一个维度就是二进制的radical inverse
+// 第二个维度的本原多项式是x + 1，方向数v[k] = v[k - 1] ^ (v[k - 1] >> 1)
+fn sobolSample(index: u32, dimension: usize) -> u32 {
+    let mut res = 0;
+    let mut v: u32 = 1 << 31;
+    let mut index = index;
+
+    while index > 0 {
+        if index & 1 == 1 {
+            res ^= v;
+        }
+        index >>= 1;
+        v = if dimension == 0 { v >> 1 } else { v ^ (v >> 1) };
+    }
+
+    return res;
+}
+
+// Owen scrambling：对二进制小数的每一位，根据更高的那些位随机决定要不要翻转，能保持Sobol序列的分层性质
+// 用Laine和Karras的hash近似 <https://psychopath.io/post/2021_01_30_building_a_better_lk_hash>
+fn owenScramble(value: u32, seed: u32) -> u32 {
+    let mut v = value.reverse_bits();
+    v ^= v.wrapping_mul(0x3d20adea);
+    v = v.wrapping_add(seed);
+    v = v.wrapping_mul((seed >> 16) | 1);
+    v ^= v.wrapping_mul(0x05526c56);
+    v ^= v.wrapping_mul(0x53a22864);
+    return v.reverse_bits();
+}
+
+// Owen scrambled Sobol：每次next2D()都用Sobol序列的前两个维度，每个pixel、每一对维度用不同的scramble
+// 前两个维度是(0, 2)-序列，前2^m个样本在任何面积是1/2^m的二进制矩形格子里正好一个点
+// 每一对维度用的都是同一组点，只换scramble的话各对之间还是相关的（比如最高位都是index的最低位再翻转一下），
+// 所以每一对维度还要先把index打乱，和pbrt的PaddedSobolSampler一样
+// 打乱index也用owenScramble，前2^m个index打乱以后还是一整块对齐的2^m个index，分层性质不变
+#[derive(Clone, Debug)]
+pub struct SobolSampler {
+    seed: u64,
+    pixel: (usize, usize),
+    index: usize,
+    dimension: usize,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed: seed,
+            pixel: (0, 0),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        return self.seed;
+    }
+
+    fn scrambled(&self, dimension: usize, which: u64) -> f64 {
+        let scramble = |which: u64| -> u32 {
+            return hash(&[
+                self.seed,
+                self.pixel.0 as u64,
+                self.pixel.1 as u64,
+                self.dimension as u64,
+                which,
+            ]) as u32;
+        };
+        // 同一对维度的两个分量要用同一个打乱的index
+        let index = owenScramble(self.index as u32, scramble(2));
+        let value = owenScramble(sobolSample(index, dimension), scramble(which));
+        return value as f64 / 4294967296.0;
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn startPixelSample(&mut self, pixel: (usize, usize), index: usize) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next1D(&mut self) -> f64 {
+        let res = self.scrambled(0, 0);
+        self.dimension += 1;
+        return res;
+    }
+
+    fn next2D(&mut self) -> (f64, f64) {
+        let res = (self.scrambled(0, 0), self.scrambled(1, 1));
+        self.dimension += 2;
+        return res;
+    }
+
+    fn seeded(&self, seed: u64) -> Box<dyn Sampler> {
+        return Box::new(Self::new(seed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sampler::HaltonSampler;
+    use crate::sampler::Sampler;
+    use crate::sampler::SobolSampler;
+    use crate::sampler::StratifiedSampler;
+
+    // 8 x 8分层和64个Sobol样本在8 x 8的格子里都是每格正好一个点，Halton积分xy的误差要比随机数小很多
+    #[test]
+    fn samplersAreWellDistributed() {
+        let mut samplers: Vec<Box<dyn Sampler>> = vec![
+            Box::new(StratifiedSampler::new(8, 8, 1)),
+            Box::new(SobolSampler::new(1)),
+        ];
+
+        for sampler in samplers.iter_mut() {
+            for dimension in 0..4 {
+                let mut counts = [[0; 8]; 8];
+                for index in 0..64 {
+                    sampler.startPixelSample((3, 5), index);
+                    for _ in 0..dimension {
+                        sampler.next2D();
+                    }
+                    let (u, v) = sampler.next2D();
+                    assert!(u >= 0.0 && u < 1.0 && v >= 0.0 && v < 1.0);
+                    counts[(v * 8.0) as usize][(u * 8.0) as usize] += 1;
+                }
+                assert_eq!(counts, [[1; 8]; 8]);
+            }
+        }
+
+        let mut sampler = HaltonSampler::new(1);
+        let n = 1024;
+        let mut sum = 0.0;
+        for index in 0..n {
+            sampler.startPixelSample((3, 5), index);
+            sampler.next2D();
+            let (u, v) = sampler.next2D();
+            sum += u * v;
+        }
+        assert!((sum / n as f64 - 0.25).abs() < 0.005);
+    }
+
+    // 不同的两对维度之间不相关：第一对的u小于0.5的时候，第二对的u小于0.5的概率应该是一半左右
+    #[test]
+    fn sobolDimensionsAreUncorrelated() {
+        let mut sampler = SobolSampler::new(1);
+        let n = 256;
+        let (mut same, mut product) = (0, 0.0);
+        for index in 0..n {
+            sampler.startPixelSample((3, 5), index);
+            let (u0, v0) = sampler.next2D();
+            let (u1, v1) = sampler.next2D();
+            if (u0 < 0.5) == (u1 < 0.5) {
+                same += 1;
+            }
+            product += (u0 - 0.5) * (u1 - 0.5) + (v0 - 0.5) * (v1 - 0.5);
+        }
+        assert!(same > n * 3 / 8 && same < n * 5 / 8);
+        // 完全相关的话是2 / 12
+        assert!((product / n as f64).abs() < 0.02);
+    }
+}
@@ -1,10 +1,13 @@
+use crate::mat4::AnimatedTransform;
 use crate::mat4::Mat4;
 use crate::mat4::Mat4Cached;
 use crate::material::Material;
 use crate::ray::Hit;
 use crate::ray::HitRecord;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 
+use std::borrow::Cow;
 use std::sync::Arc;
 
 #[derive(Clone, Debug)]
@@ -16,6 +19,8 @@ pub struct Sprite<T, U> {
     // 但是这里还是可能留下了两个问题：
     // 位移和旋转的行列式都是1，说明它们不改变原物体的体积，单位圆还是单位圆，但是如果det一旦不是1，比如一个把单位圆在垂直方向拉伸、变成椭球的矩阵，这时候表面法向量不是简单的做正变换，我记得是乘以逆变换的转置（但我找不到资料了）
     // 如何给变换后的物体生成bounding box
+    motion: Option<AnimatedTransform>, // 有的话按光线的时间取变换，transform就不用了
+    objectId: u32,                     // 输出object id的AOV用的，0表示没有编号
 }
 
 // 试试时髦的builder pattern？
@@ -48,6 +53,19 @@ impl<T, U> SpriteBuilder<T, U> {
         M: Into<Mat4Cached>,
     {
         self.sprite.transform = transform.into();
+        self.sprite.motion = None;
+        return self;
+    }
+
+    // 动起来的物体，见AnimatedTransform
+    pub fn motion(mut self, motion: AnimatedTransform) -> Self {
+        self.sprite.transform = motion.keyframes()[0].1;
+        self.sprite.motion = Some(motion);
+        return self;
+    }
+
+    pub fn objectId(mut self, objectId: u32) -> Self {
+        self.sprite.objectId = objectId;
         return self;
     }
 }
@@ -59,6 +77,8 @@ impl<T, U> Sprite<T, U> {
                 geometry: None,
                 material: None,
                 transform: Mat4::identity().into(),
+                motion: None,
+                objectId: 0,
             },
         }
     }
@@ -68,6 +88,8 @@ impl<T, U> Sprite<T, U> {
             geometry: geometry,
             material: material,
             transform: Mat4::identity().into(),
+            motion: None,
+            objectId: 0,
         }
     }
 
@@ -79,9 +101,26 @@ impl<T, U> Sprite<T, U> {
         return &self.material;
     }
 
+    // 动起来的话是第一个关键帧的变换
     pub fn transform(&self) -> &Mat4Cached {
         return &self.transform;
     }
+
+    pub fn motion(&self) -> &Option<AnimatedTransform> {
+        return &self.motion;
+    }
+
+    // 不动的物体不用每次都复制一遍矩阵
+    pub fn transformAt(&self, time: f64) -> Cow<'_, Mat4Cached> {
+        match &self.motion {
+            Some(motion) => Cow::Owned(motion.at(time)),
+            None => Cow::Borrowed(&self.transform),
+        }
+    }
+
+    pub fn objectId(&self) -> u32 {
+        return self.objectId;
+    }
 }
 
 impl<T, U> Hit for Sprite<T, U>
@@ -91,28 +130,23 @@ where
 {
     // <https://stackoverflow.com/questions/61712044/cast-arcrwlockt-to-arcrwlocktraitobject>
     // 放心了，'static并不是说在整个程序周期都有效，而是说可以放心的用，毕竟有Arc在，是不可能指向无效数据的
-    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<HitRecord> {
         if let Some(geometry) = &self.geometry {
             // 既然geometry.rs里实现了(&Hit, &Mat4).hit()，那么这里其实只要这样写就可以了
             // return (geometry.as_ref(), self.transform()).hit(ray);
 
-            if let Some(inversed) = &self.transform().inversed() {
+            let transform = self.transformAt(ray.time());
+            if let Some(inversed) = &transform.inversed() {
                 // 原光线反变换
                 let origin = ray.origin().xyz1().transformed(inversed);
                 let direction = ray.direction().xyz0().transformed(inversed);
 
-                let ray = Ray::new(origin.into(), direction.into());
+                let ray = Ray::new(origin.into(), direction.into()).withTime(ray.time());
 
-                if let Some(record) = geometry.hit(&ray) {
+                if let Some(record) = geometry.hit(&ray, sampler) {
                     // 击中后再正变换
-                    let intersection = record
-                        .intersection()
-                        .xyz1()
-                        .transformed(self.transform().as_ref());
-                    let normal = record
-                        .normal()
-                        .xyz0()
-                        .transformed(self.transform().as_ref());
+                    let intersection = record.intersection().xyz1().transformed(transform.origin());
+                    let normal = record.normal().xyz0().transformed(transform.origin());
 
                     let res = HitRecord::new(
                         record.t(),
@@ -124,7 +158,12 @@ where
                         self.material.as_ref().map(|v| v.as_ref() as &dyn Material),
                         *record.uv(),
                     );
-                    return Some(res);
+                    // Sprite套Sprite的时候，自己没编号就用里面那个的
+                    if self.objectId != 0 {
+                        return Some(res.withObjectId(self.objectId));
+                    } else {
+                        return Some(res.withObjectId(record.objectId()));
+                    }
                 } else {
                     return None;
                 }
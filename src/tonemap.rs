@@ -0,0 +1,211 @@
+use crate::environment::luminance;
+use crate::vec3::Vec3;
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+// 把线性的HDR颜色压到[0, 1]里，结果还是线性的，最后再经过sRGB的OETF变成显示用的数值
+pub trait ToneMap: Send + Sync + Debug {
+    fn map(&self, color: &Vec3) -> Vec3;
+}
+
+// 什么都不做，超过1的直接截断。注意和以前的输出不完全一样：以前是先开平方(gamma 2)再截断，
+// 现在走的是sRGB的OETF，最暗的地方会更暗一点，中间调会稍微亮一点
+#[derive(Clone, Debug, Default)]
+pub struct ClampToneMap;
+
+impl ToneMap for ClampToneMap {
+    fn map(&self, color: &Vec3) -> Vec3 {
+        return *color;
+    }
+}
+
+// 按亮度压缩，L / (1 + L)，亮度到white的时候正好映射到1。white是无穷大就是最原始的Reinhard
+// 只缩放亮度不动色相，不会像每个通道分别压缩那样把颜色洗白
+// <https://www.cs.utah.edu/docs/techreports/2002/pdf/UUCS-02-001.pdf>
+#[derive(Clone, Debug)]
+pub struct ReinhardToneMap {
+    white: f64,
+}
+
+impl ReinhardToneMap {
+    pub fn new(white: f64) -> Self {
+        Self { white: white }
+    }
+
+    pub fn white(&self) -> f64 {
+        return self.white;
+    }
+}
+
+impl Default for ReinhardToneMap {
+    fn default() -> Self {
+        Self::new(f64::INFINITY)
+    }
+}
+
+impl ToneMap for ReinhardToneMap {
+    fn map(&self, color: &Vec3) -> Vec3 {
+        let l = luminance(color);
+        if l <= 0.0 {
+            return Vec3::new(0.0, 0.0, 0.0);
+        }
+
+        let mapped = l * (1.0 + l / (self.white * self.white)) / (1.0 + l);
+        return *color * (mapped / l);
+    }
+}
+
+// ACES filmic曲线，用的是Stephen Hill拟合的RRT + ODT，输入输出都是线性的sRGB
+// 暗部有一点压低，亮部慢慢过渡到白色，高光里的颜色也会逐渐变淡，像胶片一样
+// <https://github.com/TheRealMJP/BakingLab/blob/master/BakingLab/ACES.hlsl>
+#[derive(Clone, Debug, Default)]
+pub struct AcesToneMap;
+
+impl AcesToneMap {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    const INPUT: [[f64; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    const OUTPUT: [[f64; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+
+    fn multiply(matrix: &[[f64; 3]; 3], color: &Vec3) -> Vec3 {
+        let row = |i: usize| -> f64 {
+            matrix[i][0] * color.r() + matrix[i][1] * color.g() + matrix[i][2] * color.b()
+        };
+        return Vec3::new(row(0), row(1), row(2));
+    }
+
+    fn curve(x: f64) -> f64 {
+        let a = x * (x + 0.0245786) - 0.000090537;
+        let b = x * (0.983729 * x + 0.4329510) + 0.238081;
+        return a / b;
+    }
+}
+
+impl ToneMap for AcesToneMap {
+    fn map(&self, color: &Vec3) -> Vec3 {
+        let color = Self::multiply(&Self::INPUT, color);
+        let color = Vec3::new(
+            Self::curve(color.r()),
+            Self::curve(color.g()),
+            Self::curve(color.b()),
+        );
+        return Self::multiply(&Self::OUTPUT, &color);
+    }
+}
+
+// 线性的film变成8位图像：先乘上2^exposure，再tone mapping，最后sRGB编码
+#[derive(Clone, Debug)]
+pub struct DisplayTransform {
+    exposure: f64, // 单位是档（stop），+1亮一倍
+    toneMap: Arc<dyn ToneMap>,
+}
+
+impl DisplayTransform {
+    pub fn new(exposure: f64, toneMap: Arc<dyn ToneMap>) -> Self {
+        Self {
+            exposure: exposure,
+            toneMap: toneMap,
+        }
+    }
+
+    pub fn exposure(&self) -> f64 {
+        return self.exposure;
+    }
+
+    pub fn toneMap(&self) -> &Arc<dyn ToneMap> {
+        return &self.toneMap;
+    }
+
+    // 返回的还是[0, 1]上的浮点数，已经是sRGB编码过的
+    pub fn apply(&self, color: &Vec3) -> Vec3 {
+        let color = self.toneMap.map(&(*color * 2.0_f64.powf(self.exposure)));
+        return Vec3::new(
+            linearToSrgb(color.r()),
+            linearToSrgb(color.g()),
+            linearToSrgb(color.b()),
+        );
+    }
+
+    pub fn apply8(&self, color: &Vec3) -> [u8; 3] {
+        let color = self.apply(color);
+        return [
+            (color.r() * 255.0).round() as u8,
+            (color.g() * 255.0).round() as u8,
+            (color.b() * 255.0).round() as u8,
+        ];
+    }
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self::new(0.0, Arc::new(ClampToneMap))
+    }
+}
+
+// sRGB的OETF，暗部是一小段直线，其余是2.4次方的曲线，不在[0, 1]里的先截断
+// <https://en.wikipedia.org/wiki/SRGB#Transformation>
+pub fn linearToSrgb(value: f64) -> f64 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.0031308 {
+        return 12.92 * value;
+    } else {
+        return 1.055 * value.powf(1.0 / 2.4) - 0.055;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tonemap::linearToSrgb;
+    use crate::tonemap::AcesToneMap;
+    use crate::tonemap::ClampToneMap;
+    use crate::tonemap::DisplayTransform;
+    use crate::tonemap::ReinhardToneMap;
+    use crate::tonemap::ToneMap;
+    use crate::vec3::Vec3;
+
+    use std::sync::Arc;
+
+    // 越亮的输入映射出来也越亮。ACES拟合的曲线最后会稍微超过1一点点，反正sRGB编码的时候会截断
+    // 带white的Reinhard超过white以后会继续变亮，本来就是想让它在那里过曝
+    #[test]
+    fn toneMapsAreMonotonic() {
+        assert_eq!(linearToSrgb(0.0), 0.0);
+        assert!((linearToSrgb(0.18) - 0.4613).abs() < 1e-4);
+        assert!((linearToSrgb(1.0) - 1.0).abs() < 1e-12);
+        assert!((linearToSrgb(50.0) - 1.0).abs() < 1e-12);
+
+        let toneMaps: Vec<(Arc<dyn ToneMap>, f64)> = vec![
+            (Arc::new(ReinhardToneMap::default()), 1.0),
+            (Arc::new(ReinhardToneMap::new(4.0)), f64::INFINITY),
+            (Arc::new(AcesToneMap), 1.02),
+        ];
+        for (toneMap, limit) in toneMaps.iter() {
+            let mut last = -1.0;
+            for i in 0..=100 {
+                let x = 0.01 * 1.15_f64.powi(i);
+                let y = toneMap.map(&Vec3::new(x, x, x)).g();
+                assert!(y > last && y <= *limit, "{:?} {} {}", toneMap, x, y);
+                last = y;
+            }
+        }
+        assert!((ReinhardToneMap::new(4.0).map(&Vec3::new(4.0, 4.0, 4.0)).r() - 1.0).abs() < 1e-12);
+
+        // +1档正好亮一倍
+        let brighter = DisplayTransform::new(1.0, Arc::new(ClampToneMap));
+        assert_eq!(brighter.apply8(&Vec3::new(0.25, 0.0, 1.0)), [188, 0, 255]);
+        assert_eq!(
+            DisplayTransform::default().apply8(&Vec3::new(0.5, 0.0, 1.0)),
+            [188, 0, 255]
+        );
+    }
+}
@@ -1,17 +1,26 @@
+use crate::sampler::Sampler;
 use crate::vec3::Vec3;
-use rand::random;
-use rand::thread_rng;
-use rand::Rng;
 
-pub fn randomInUnitSphere() -> Vec3 {
-    let mut p = Vec3::new(1.0, 1.0, 1.0);
+use std::f64::consts::PI;
 
-    while p.dot(&p) >= 1.0 {
-        p = Vec3::new(random::<f64>(), random::<f64>(), random::<f64>()) * 2.0
-            - Vec3::new(1.0, 1.0, 1.0);
-    }
+// 下面几个都不用拒绝采样了，拒绝采样每次用掉的随机数个数不固定，Halton、Sobol这些低差异序列的维度就对不上了
+// 都是直接把[0, 1)^2上的点变换过去，这样sampler里分布得均匀的点变换完之后也是均匀的
+
+// 球体内均匀分布：方向均匀，半径取立方根，因为半径r以内的体积正比于r^3
+pub fn randomInUnitSphere(sampler: &mut dyn Sampler) -> Vec3 {
+    let direction = randomUnitVector(sampler);
+    let radius = sampler.next1D().cbrt();
+    return direction * radius;
+}
 
-    return p;
+// 单位球面上均匀分布的向量。normal + randomUnitVector()正好是按cos分布的，也就是理想的Lambertian
+// 阿基米德帽盒定理：z在[-1, 1]上均匀分布，球面上的点就是均匀的
+pub fn randomUnitVector(sampler: &mut dyn Sampler) -> Vec3 {
+    let (u, v) = sampler.next2D();
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    return Vec3::new(r * phi.cos(), r * phi.sin(), z);
 }
 // 书上用的是这个奇怪的球面向量生成器，但是我感觉这不就是一个很简单的变换吗……
 
@@ -24,19 +33,35 @@ pub fn randomInUnitSphere() -> Vec3 {
 // }
 // 试了下，果然不是很均匀……
 
-pub fn randomInUnitDisk() -> Vec3 {
-    let mut generator = thread_rng();
-
-    loop {
-        let p = Vec3::new(
-            generator.gen_range(-1.0, 1.0),
-            generator.gen_range(-1.0, 1.0),
-            0.0,
-        );
-        if p.length() >= 1.0 {
-            continue;
-        } else {
-            return p;
-        }
+// Shirley和Chiu的同心圆映射，正方形一圈一圈地映射到圆盘上，相邻的点映射完还是相邻的，变形比极坐标小
+// <http://www.pbr-book.org/3ed-2018/Monte_Carlo_Integration/2D_Sampling_with_Multidimensional_Transformations.html#SamplingaUnitDisk>
+pub fn randomInUnitDisk(sampler: &mut dyn Sampler) -> Vec3 {
+    let (u, v) = sampler.next2D();
+    let (a, b) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+
+    if a == 0.0 && b == 0.0 {
+        return Vec3::new(0.0, 0.0, 0.0);
     }
+
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4.0 * (b / a))
+    } else {
+        (b, PI / 2.0 - PI / 4.0 * (a / b))
+    };
+    return Vec3::new(r * theta.cos(), r * theta.sin(), 0.0);
+}
+
+// 外接圆半径是1的正sides边形里均匀分布的点，第一个顶点在rotation方向上，z是0
+// 多边形从中心切成sides个一样的三角形，u挑一个三角形，剩下的小数部分和v在三角形里均匀取点
+pub fn randomInRegularPolygon(sides: usize, rotation: f64, sampler: &mut dyn Sampler) -> Vec3 {
+    let (u, v) = sampler.next2D();
+    let position = u * sides as f64;
+    let index = (position.floor() as usize).min(sides - 1);
+    let a = (position - index as f64).sqrt();
+
+    let vertex = |i: usize| -> Vec3 {
+        let angle = rotation + 2.0 * PI * i as f64 / sides as f64;
+        return Vec3::new(angle.cos(), angle.sin(), 0.0);
+    };
+    return (vertex(index) * (1.0 - v) + vertex(index + 1) * v) * a;
 }
@@ -2,7 +2,7 @@ use crate::vec4::Vec4;
 
 use std::ops::*;
 
-#[derive(Copy, Debug, Clone)]
+#[derive(Copy, Debug, Clone, PartialEq)]
 pub struct Vec3 {
     x: f64,
     y: f64,
@@ -258,6 +258,18 @@ impl Div<f64> for Vec3 {
     }
 }
 
+impl Div<Vec3> for Vec3 {
+    type Output = Vec3;
+
+    fn div(self, other: Vec3) -> Vec3 {
+        Vec3 {
+            x: self.x / other.x,
+            y: self.y / other.y,
+            z: self.z / other.z,
+        }
+    }
+}
+
 impl DivAssign<f64> for Vec3 {
     fn div_assign(&mut self, other: f64) {
         self.x /= other;
@@ -4,13 +4,10 @@ use crate::material::Texture;
 use crate::ray::Hit;
 use crate::ray::HitRecord;
 use crate::ray::Ray;
+use crate::sampler::Sampler;
 use crate::sprite::Sprite;
 use crate::vec3::Vec3;
 
-use rand::random;
-use rand::thread_rng;
-use rand::Rng;
-
 use std::sync::Arc;
 
 // 本来想把烟雾做成某种material，但是没有办法单独给带烟雾的Sprite实现Hit，所以烟雾只能变成某种geometry了
@@ -43,56 +40,61 @@ where
 {
     // 实现烟雾的大概思路是，不要在表面scatter，而是在物体的内部、在光束飞行的路线上随机选一点来scatter
     // 我不知道书上的代码是怎么处理光束从物体内部发出的情况的，书上这一段的最下面写了一句从内部出发也是需要处理的，但是代码里好像完全没有处理，直接就return false了
-    fn hit(&self, ray: &Ray) -> Option<HitRecord> {
-        if let Some(record1) = self.boundary.hit(&ray) {
+    fn hit(&self, ray: &Ray, sampler: &mut dyn Sampler) -> Option<HitRecord> {
+        if let Some(record1) = self.boundary.hit(&ray, sampler) {
             // 第一次hit
             if record1.normal().dot(ray.direction()) < 0.0 {
                 // 第一次hit是进入物体
                 let ray = Ray::new(
                     record1.intersection().clone() + *ray.direction() * 1e-6,
                     ray.direction().clone(),
-                );
-                if let Some(record2) = self.boundary.hit(&ray) {
+                )
+                .withTime(ray.time());
+                if let Some(record2) = self.boundary.hit(&ray, sampler) {
                     // 第二次hit
                     let distanceInsideGeometry = record2.t(); // 光束在geometry内部飞行的距离
-                    let mut generator = thread_rng();
-                    let distance =
-                        (-1.0 / self.density) * generator.gen_range(0.0 as f64, 1.0 as f64).ln(); // 为什么书这里要取log
+
+                    // 1 - u是为了不会取到ln(0)
+                    let distance = (-1.0 / self.density) * (1.0 - sampler.next1D()).ln(); // 为什么书这里要取log
                     if distance > distanceInsideGeometry {
                         return None;
                     }
                     let mut uv = *record1.uv();
                     uv.0 = uv.0 + record2.uv().0;
                     uv.1 = uv.1 + record2.uv().1;
-                    return Some(HitRecord::new(
-                        record1.t() + distance,
-                        ray.at(record1.t() + distance),
-                        (*record1.normal() + *record2.normal()) / 2.0,
-                        None,
-                        uv,
-                    ));
+                    return Some(
+                        HitRecord::new(
+                            record1.t() + distance,
+                            ray.at(record1.t() + distance),
+                            (*record1.normal() + *record2.normal()) / 2.0,
+                            None,
+                            uv,
+                        )
+                        .withObjectId(record1.objectId()),
+                    );
                 } else {
                     return None;
                 }
             } else {
                 // 第一次hit就是从物体中出去了，那么说明射线的起点本身就在物体内部
                 let distanceInsideGeometry = record1.t(); // 光束在geometry内部飞行的距离
-                let mut generator = thread_rng();
-                let distance =
-                    (-1.0 / self.density) * generator.gen_range(0.0 as f64, 1.0 as f64).ln(); // 为什么书这里要取log
+                let distance = (-1.0 / self.density) * (1.0 - sampler.next1D()).ln(); // 为什么书这里要取log
                 if distance > distanceInsideGeometry {
                     return None;
                 }
                 let mut uv = *record1.uv();
                 uv.0 = uv.0;
                 uv.1 = uv.1;
-                return Some(HitRecord::new(
-                    distance, // 这里原来写错了
-                    ray.at(distance),
-                    record1.normal().clone(),
-                    None,
-                    uv,
-                ));
+                return Some(
+                    HitRecord::new(
+                        distance, // 这里原来写错了
+                        ray.at(distance),
+                        record1.normal().clone(),
+                        None,
+                        uv,
+                    )
+                    .withObjectId(record1.objectId()),
+                );
             }
         } else {
             return None;